@@ -3,7 +3,7 @@ mod util;
 
 use glm::vec2;
 use plox::atlas::Atlas;
-use plox::font;
+use plox::font::{self, FontRegistry, Style};
 use plox::gpu::{
     circle::{CircleElement, CircleRenderer, CircleShader},
     shader::Shader,
@@ -223,17 +223,20 @@ impl Thing {
 /// its linear spline component by completely re-calculating the
 /// tesselation.
 unsafe fn retesselate(state: &mut State) {
+    // Use the compute shader if we have it.
+    let tesselator = state.line_renderer.cubic_tesselator.as_ref();
+
     for (bez, tess, mb_scale) in state
         .ecs
         .content
         .iter_mut()
         .filter_map(Thing::bezier_component)
     {
-        let spline = Segment::spline(&bez.sample());
         let scale = mb_scale.unwrap_or(1.0);
         if let Some(tess) = tess {
-            tess.update(spline.segments(), scale);
+            tess.update_cubic(bez, scale, tesselator);
         } else {
+            let spline = Segment::spline(&bez.sample());
             tess.replace(LineElement::new(spline.segments(), scale));
         };
     }
@@ -299,7 +302,9 @@ impl<'a> State<'a> {
         let circle_renderer = CircleRenderer::new();
         let line_renderer = LineRenderer::new();
 
-        let atlas = Arc::new(Atlas::new(&font::LM_MATH));
+        let mut fonts = FontRegistry::latin_modern();
+        let atlas = fonts.atlas(font::LATIN_MODERN_MATH, Style::Regular).unwrap();
+        let roman = fonts.atlas(font::LATIN_MODERN_ROMAN, Style::Regular).unwrap();
         let default_text_shader = Shader::simple_blit();
 
         // Shared mouse position
@@ -335,7 +340,7 @@ impl<'a> State<'a> {
         ecs.push(
             Thing::new()
                 .typeset_text(Typeset::elem(Arc::new(RwLock::new(TextElement::new(
-                    "SVG:", &atlas,
+                    "SVG:", &roman,
                 )))))
                 .translate(vec2(10.0, 220.0))
                 .scale(40.0),
@@ -0,0 +1,340 @@
+//! Arrowheads.
+//!
+//! An arrowhead is a small filled contour sitting at the end of a polyline. The contours are
+//! built as quadratic splines, so they can go through the same α-texture pipeline as glyphs and
+//! SVGs, and get exactly the same anti-aliasing.
+//!
+//! The line itself has to be shortened a bit, otherwise the square cap of the stroke pokes out
+//! through the tip of the arrow.
+use crate::atlas::Outline;
+use crate::spline::{Rect, Spline};
+use glm::Vec2;
+use ttf_parser::OutlineBuilder;
+
+/// Points closer than this are the same point, as far as the direction of a head goes.
+const EPS: f32 = 1e-4;
+
+/// The shape of an arrowhead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrowStyle {
+    /// Plain filled isosceles triangle.
+    Triangle,
+    /// Triangle with a notch in the back (TikZ' `stealth`).
+    Stealth,
+    /// Two barbs stroked with the same thickness as the line. (`->`)
+    Open,
+    /// Curved sides and a concave back, like the default LaTeX arrow.
+    Latex,
+}
+
+/// A single arrowhead.
+#[derive(Debug, Clone, Copy)]
+pub struct Arrowhead {
+    pub style: ArrowStyle,
+    // Explicit dimensions. If these are not set, they are derived from the
+    // width of the line the head is attached to.
+    length: Option<f32>,
+    width: Option<f32>,
+}
+
+/// Arrowheads at either end of a polyline.
+#[derive(Debug, Clone, Copy)]
+pub struct Arrows {
+    pub start: Option<Arrowhead>,
+    pub end: Option<Arrowhead>,
+}
+
+impl Arrowhead {
+    pub fn new(style: ArrowStyle) -> Self {
+        Arrowhead {
+            style,
+            length: None,
+            width: None,
+        }
+    }
+
+    /// Set the length of the head, from the tip to the back.
+    pub fn length(mut self, length: f32) -> Self {
+        self.length = Some(length);
+        self
+    }
+
+    /// Set the total width of the head across its back.
+    pub fn width(mut self, width: f32) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    /// The dimensions (length, half-width) of the head on a line of the given width.
+    /// Note that like in the tesselator, the line `width` is *half* the stroke thickness.
+    fn dims(&self, width: f32) -> (f32, f32) {
+        // Roughly what TikZ does: A fixed part and a part proportional to the line.
+        let l = self.length.unwrap_or(3.0 + 9.0 * width);
+        let h = self.width.map(|w| w / 2.0).unwrap_or(0.4 * l);
+        (l, h)
+    }
+
+    /// How much the line has to be shortened at this end, such that the line ends inside the
+    /// head, and the cap of the stroke does not poke through the tip.
+    pub fn inset(&self, width: f32) -> f32 {
+        let (l, h) = self.dims(width);
+        match self.style {
+            ArrowStyle::Triangle | ArrowStyle::Latex => 0.5 * l + width,
+            ArrowStyle::Stealth => 0.6 * l + width,
+            ArrowStyle::Open => {
+                // End the line where the inner edges of the barbs meet.
+                let sin = h / f32::hypot(l, h);
+                2.0 * width / sin + width
+            }
+        }
+    }
+
+    /// Trace the contour of the head with its tip at `tip`, pointing in the direction `dir`.
+    pub fn trace(&self, builder: &mut impl OutlineBuilder, tip: Vec2, dir: Vec2, width: f32) {
+        let (l, h) = self.dims(width);
+
+        // uv-basis for the local space of the head. The tip is at the origin and the head is
+        // pointing in the positive u-direction.
+        let u = dir.normalize();
+        let v = glm::vec2(-u.y, u.x);
+        let p = |x: f32, y: f32| tip + x * u + y * v;
+
+        let mut move_to = |q: Vec2| builder.move_to(q.x, q.y);
+        match self.style {
+            ArrowStyle::Triangle => {
+                move_to(p(0.0, 0.0));
+                let mut line_to = |q: Vec2| builder.line_to(q.x, q.y);
+                line_to(p(-l, h));
+                line_to(p(-l, -h));
+            }
+            ArrowStyle::Stealth => {
+                move_to(p(0.0, 0.0));
+                let mut line_to = |q: Vec2| builder.line_to(q.x, q.y);
+                line_to(p(-l, h));
+                line_to(p(-0.7 * l, 0.0));
+                line_to(p(-l, -h));
+            }
+            ArrowStyle::Latex => {
+                move_to(p(0.0, 0.0));
+                let mut quad_to = |c: Vec2, q: Vec2| builder.quad_to(c.x, c.y, q.x, q.y);
+                quad_to(p(-0.45 * l, 0.3 * h), p(-l, h));
+                quad_to(p(-0.7 * l, 0.0), p(-l, -h));
+                quad_to(p(-0.45 * l, -0.3 * h), p(0.0, 0.0));
+            }
+            ArrowStyle::Open => {
+                // The barbs have the same thickness as the stroke.
+                let t = 2.0 * width;
+                let hyp = f32::hypot(l, h);
+                let (sin, cos) = (h / hyp, l / hyp);
+                // The inner edges meet on the axis, and each is parallel to its outer edge.
+                let j = -t / sin;
+                move_to(p(0.0, 0.0));
+                let mut line_to = |q: Vec2| builder.line_to(q.x, q.y);
+                line_to(p(-l, h));
+                line_to(p(-l + t * sin, h - t * cos));
+                line_to(p(j, 0.0));
+                line_to(p(-l + t * sin, -h + t * cos));
+                line_to(p(-l, -h));
+            }
+        }
+        builder.close();
+    }
+}
+
+impl Arrows {
+    pub fn none() -> Self {
+        Arrows {
+            start: None,
+            end: None,
+        }
+    }
+
+    /// Arrowhead at the end of the line.
+    pub fn end(head: Arrowhead) -> Self {
+        Arrows {
+            start: None,
+            end: Some(head),
+        }
+    }
+
+    /// Arrowhead at the start of the line.
+    pub fn start(head: Arrowhead) -> Self {
+        Arrows {
+            start: Some(head),
+            end: None,
+        }
+    }
+
+    /// The same arrowhead at both ends.
+    pub fn both(head: Arrowhead) -> Self {
+        Arrows {
+            start: Some(head),
+            end: Some(head),
+        }
+    }
+
+    /// Attach the arrowheads to the polyline `pts` stroked with the given `width`.
+    /// Returns the shortened polyline and the outline of the heads, in the same coordinate
+    /// system as the input.
+    pub fn attach(&self, pts: &[Vec2], width: f32) -> (Vec<Vec2>, Outline) {
+        let mut builder = Spline::builder();
+        let mut pts = pts.to_vec();
+
+        if pts.len() < 2 {
+            return (pts, outline(builder.build()));
+        }
+
+        let total: f32 = pts.windows(2).map(|p| (p[1] - p[0]).norm()).sum();
+        let mut s0 = self.start.map(|h| h.inset(width)).unwrap_or(0.0);
+        let mut s1 = self.end.map(|h| h.inset(width)).unwrap_or(0.0);
+
+        // If the line is too short for the heads, we still want to keep a tiny bit of it,
+        // otherwise we would end up with degenerate segments.
+        if s0 + s1 > 0.99 * total {
+            let k = 0.99 * total / (s0 + s1);
+            s0 *= k;
+            s1 *= k;
+        }
+
+        // The heads are oriented along the chord between the tip and the point one head-length
+        // down the line. For a sampled curve this is a lot more stable than the last segment.
+        // A line where all points coincide has no direction, and gets no heads.
+        if let Some(head) = self.end {
+            let tip = pts[pts.len() - 1];
+            let (l, _) = head.dims(width);
+            if let Some(dir) = direction(pts.iter().rev(), l) {
+                head.trace(&mut builder, tip, dir, width);
+            }
+        }
+
+        if let Some(head) = self.start {
+            let tip = pts[0];
+            let (l, _) = head.dims(width);
+            if let Some(dir) = direction(pts.iter(), l) {
+                head.trace(&mut builder, tip, dir, width);
+            }
+        }
+
+        trim(&mut pts, s1);
+        pts.reverse();
+        trim(&mut pts, s0);
+        pts.reverse();
+
+        (pts, outline(builder.build()))
+    }
+}
+
+/// The direction of a head at the first point of a polyline, from the point a distance `l`
+/// down the line to the tip. If those coincide (the line loops back onto the tip), this is the
+/// direction of the first segment that has a length. `None` if all points coincide.
+fn direction<'a>(pts: impl Iterator<Item = &'a Vec2> + Clone, l: f32) -> Option<Vec2> {
+    let mut rest = pts.clone();
+    let tip = *rest.next()?;
+    let chord = tip - walk(pts, l);
+    if chord.norm() >= EPS {
+        return Some(chord);
+    }
+    rest.map(|&q| tip - q).find(|d| d.norm() >= EPS)
+}
+
+/// Walk a distance `d` along a polyline, and return the point we end up at.
+/// If the polyline is shorter than `d`, this is the last point.
+fn walk<'a>(mut pts: impl Iterator<Item = &'a Vec2>, mut d: f32) -> Vec2 {
+    let mut p = *pts.next().unwrap();
+    for &q in pts {
+        let l = (q - p).norm();
+        if l >= d && l > 0.0 {
+            return p + (d / l) * (q - p);
+        }
+        d -= l;
+        p = q;
+    }
+    p
+}
+
+/// Remove a length `d` from the end of a polyline.
+fn trim(pts: &mut Vec<Vec2>, mut d: f32) {
+    while pts.len() >= 2 {
+        let q = pts[pts.len() - 1];
+        let p = pts[pts.len() - 2];
+        let l = (q - p).norm();
+        if l > d {
+            let n = pts.len();
+            pts[n - 1] = q + (d / l) * (p - q);
+            return;
+        }
+        d -= l;
+        pts.pop();
+    }
+}
+
+/// Turn the spline of the heads into a renderable outline.
+fn outline(spline: Spline) -> Outline {
+    let mut verts: Vec<(f32, f32)> = Vec::with_capacity(3 * spline.len());
+    for bez in spline.strokes() {
+        verts.push(bez.0.into());
+        verts.push(bez.1.into());
+        verts.push(bez.2.into());
+    }
+
+    // An empty spline has an inverted (infinite) bounding box.
+    let bbox = if spline.len() > 0 {
+        *spline.bbox()
+    } else {
+        Rect {
+            x0: 0.0,
+            x1: 0.0,
+            y0: 0.0,
+            y1: 0.0,
+        }
+    };
+
+    Outline {
+        ctrl_pts: verts,
+        bbox,
+        glyphs: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finite(outline: &Outline) -> bool {
+        outline
+            .ctrl_pts
+            .iter()
+            .all(|p| p.0.is_finite() && p.1.is_finite())
+    }
+
+    #[test]
+    fn coincident_points_get_no_head() {
+        let arrows = Arrows::both(Arrowhead::new(ArrowStyle::Latex));
+        let p = glm::vec2(1.0, 2.0);
+        let (_, heads) = arrows.attach(&[p, p, p], 1.0);
+        assert!(heads.ctrl_pts.is_empty());
+    }
+
+    #[test]
+    fn loop_back_onto_tip_is_finite() {
+        // The point one head length down the line is the tip itself.
+        let arrows = Arrows::end(Arrowhead::new(ArrowStyle::Triangle).length(2.0));
+        let pts = [
+            glm::vec2(0.0, 0.0),
+            glm::vec2(1.0, 0.0),
+            glm::vec2(0.0, 0.0),
+        ];
+        let (_, heads) = arrows.attach(&pts, 0.5);
+        assert!(!heads.ctrl_pts.is_empty());
+        assert!(finite(&heads));
+    }
+
+    #[test]
+    fn short_line_is_finite() {
+        let arrows = Arrows::both(Arrowhead::new(ArrowStyle::Open));
+        let pts = [glm::vec2(0.0, 0.0), glm::vec2(0.1, 0.0)];
+        let (line, heads) = arrows.attach(&pts, 1.0);
+        assert!(finite(&heads));
+        assert!(line.iter().all(|p| p.x.is_finite() && p.y.is_finite()));
+    }
+}
@@ -1,97 +1,937 @@
 //! A font Atlas.
+//!
+//! The glyph outlines are built lazily: A glyph is outlined the first time it is used, and kept
+//! for the lifetime of the atlas. Math fonts have thousands of glyphs, and a figure typically
+//! uses a few dozen of them.
+use crate::color;
+use crate::metrics::{FontMetrics, Measurement, RunMetrics};
+use crate::shaping::{self, Glyph, ShapingOptions, NOTDEF};
 use crate::spline::{Point, Quadratic, Rect, Spline};
+use crate::style::Synthesis;
+use crate::variation::{self, Variation};
 use rayon::prelude::*;
 use rustybuzz::Face;
+use std::collections::HashSet;
+use std::io::{self, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
 use ttf_parser as ttf;
 
 pub struct Atlas<'a> {
-    pub outlines: Vec<Quadratic>,
-    pub bboxes: Vec<Rect>,
-    pub lut: Vec<(usize, usize)>,
-    pub face: &'a Face<'a>,
+    // One slot per glyph id, filled on first use.
+    glyphs: Vec<OnceLock<Spline>>,
+    // `font_hash` of the face, computed on first use.
+    hash: OnceLock<u64>,
+    /// The atlas has its own copy of the face, since the face holds the coordinates of
+    /// variable fonts. (Copying a face is cheap, the font data is shared.)
+    pub face: Face<'a>,
+}
+
+/// Identifies the files written by `Atlas::save`.
+const MAGIC: &[u8; 4] = b"PLXA";
+/// Version of the cubic to quadratic conversion in `spline::Builder`. Bump this whenever the
+/// conversion changes, so stale cache files are rebuilt instead of loaded.
+const CONVERSION: u32 = 1;
+
+/// A chain of atlases. Characters the first face has no glyph for are shaped with the next
+/// face in the chain, and so on. The last face is used no matter what.
+pub struct Fallback<'a> {
+    atlases: Vec<Arc<Atlas<'a>>>,
 }
 
 #[derive(Debug)]
 pub struct Outline {
     pub ctrl_pts: Vec<(f32, f32)>,
     pub bbox: Rect,
+    /// Where each glyph ended up, in the order they were laid out. Empty for outlines that
+    /// don't come from text, like SVGs.
+    pub glyphs: Vec<GlyphSpan>,
+}
+
+/// A layer of coloured text. (See `Atlas::color_layers`)
+#[derive(Debug)]
+pub struct ColorLayer {
+    pub outline: Outline,
+    /// Colour of the layer, or `None` for the colour of the text.
+    pub color: Option<glm::Vec4>,
+}
+
+/// A single glyph within an outline.
+#[derive(Debug, Clone)]
+pub struct GlyphSpan {
+    pub glyph_id: usize,
+    /// The control points of the glyph, as a range into `Outline::ctrl_pts`.
+    pub ctrl: Range<usize>,
+    /// The bytes of the text the glyph was made from. Ligatures share their cluster between
+    /// several characters, and some characters are made from several glyphs.
+    pub cluster: Range<usize>,
+    /// Pen position of the glyph, i. e. where its origin sits on the baseline.
+    pub pen: (f32, f32),
+    /// How far the pen moves along the line after the glyph. (See `shaping::Glyph::advance`)
+    pub advance: f32,
+    /// Bounding box of the glyph at its position.
+    pub bbox: Rect,
+    /// The glyph is part of vertical text, so the pen moves down instead of to the right.
+    pub vertical: bool,
 }
 
 impl<'a> Atlas<'a> {
     /// Create a new font atlas from a given font face.
-    /// This is a relatively expensive operation!
-    pub fn new(face: &'a Face) -> Atlas<'a> {
-        // We store the arcs in a coordinate system of 1em to get consistency between fonts.
-        let em = face.units_per_em() as f32;
-        let n = face.number_of_glyphs();
-
-        let mut outlines = Vec::with_capacity(10_000);
-        let mut bboxes = Vec::with_capacity(1_000);
-        let mut lut = Vec::with_capacity(1_000);
-
-        let splines: Vec<Spline> = (0..n)
-            // ~3x speedup on my 4-core machine.
-            .into_par_iter()
-            .map(|id| {
-                let mut builder = Spline::builder().scale(1.0 / em);
-                face.outline_glyph(ttf::GlyphId(id), &mut builder);
-                let spline = builder.build();
-                spline
-            })
+    /// This is cheap, the glyphs are only outlined when they are needed. (See `prefetch`)
+    pub fn new(face: &Face<'a>) -> Atlas<'a> {
+        let n = face.number_of_glyphs() as usize;
+        Atlas {
+            glyphs: (0..n).map(|_| OnceLock::new()).collect(),
+            hash: OnceLock::new(),
+            face: face.clone(),
+        }
+    }
+
+    /// Create an atlas of an instance of a variable font, for example
+    /// `&[variation::weight(700.0)]`. Axes the face does not have are ignored.
+    pub fn with_variations(face: &Face<'a>, variations: &[Variation]) -> Atlas<'a> {
+        let mut face = face.clone();
+        face.set_variations(variations);
+        Atlas::new(&face)
+    }
+
+    /// Create an atlas of a named instance of a variable font, like "Bold Condensed".
+    /// Returns `None` if the face has no instance by that name.
+    pub fn instance(face: &Face<'a>, name: &str) -> Option<Atlas<'a>> {
+        let instance = variation::named_instances(face)
+            .into_iter()
+            .find(|i| i.name == name)?;
+        Some(Atlas::with_variations(face, &instance.coords))
+    }
+
+    /// An atlas of another point on the axes of a variable font. Axes not in `variations`
+    /// stay where they are in this atlas. The glyphs that have been outlined so far are
+    /// outlined again, so this can be used to animate an axis, as long as the text does not
+    /// use too many glyphs. (See `FontRegistry::set_variations` for atlases in a registry)
+    pub fn vary(&self, variations: &[Variation]) -> Atlas<'a> {
+        let atlas = Atlas::with_variations(&self.face, variations);
+
+        let loaded: Vec<usize> = (0..self.glyphs.len())
+            .filter(|&id| self.glyphs[id].get().is_some())
+            .collect();
+
+        loaded.into_par_iter().for_each(|id| {
+            atlas.glyph(id);
+        });
+        atlas
+    }
+
+    /// The outline of a glyph, in a coordinate system of 1em.
+    /// Builds the outline if this is the first time the glyph is used. Ids that are not in
+    /// the face get the (empty) outline of .notdef.
+    pub fn glyph(&self, id: usize) -> &Spline {
+        let id = if id < self.glyphs.len() { id } else { NOTDEF };
+        self.glyphs[id].get_or_init(|| {
+            // We store the arcs in a coordinate system of 1em to get consistency between fonts.
+            let em = self.face.units_per_em() as f32;
+            let mut builder = Spline::builder().scale(1.0 / em);
+            self.face.outline_glyph(ttf::GlyphId(id as u16), &mut builder);
+            builder.build()
+        })
+    }
+
+    /// The curves of a glyph, in a coordinate system of 1em.
+    ///
+    /// This replaces the `outlines` and `lut` fields, from when the atlas outlined every glyph
+    /// up front: `&atlas.outlines[lut[id].0..lut[id].1]` is now `atlas.glyph_outline(id)`.
+    pub fn glyph_outline(&self, id: usize) -> impl Iterator<Item = &Quadratic> {
+        self.glyph(id).strokes()
+    }
+
+    /// The bounding box of the outline of a glyph, in a coordinate system of 1em.
+    ///
+    /// This replaces the `bboxes` field: `atlas.bboxes[id]` is now `atlas.glyph_bbox(id)`.
+    pub fn glyph_bbox(&self, id: usize) -> Rect {
+        *self.glyph(id).bbox()
+    }
+
+    /// Outline all glyphs that are needed to render `text` in parallel, so they don't have to
+    /// be built one by one on first use. This includes the glyphs the characters map to
+    /// directly, and whatever the shaper substitutes for them (ligatures, for example).
+    pub fn prefetch(&self, text: &str) {
+        let mut ids: Vec<usize> = text
+            .chars()
+            .filter_map(|c| self.face.glyph_index(c))
+            .map(|id| id.0 as usize)
+            .chain(shaping::shape(text, &self.face).iter().map(|g| g.glyph_id))
             .collect();
+        ids.sort_unstable();
+        ids.dedup();
+
+        // ~3x speedup on my 4-core machine.
+        ids.into_par_iter().for_each(|id| {
+            self.glyph(id);
+        });
+    }
+
+    /// Outline every glyph in the face. This is what the atlas used to do on creation, and
+    /// it is a relatively expensive operation!
+    pub fn prefetch_all(&self) {
+        (0..self.glyphs.len()).into_par_iter().for_each(|id| {
+            self.glyph(id);
+        });
+    }
 
-        for spline in splines {
-            // The range [start, end) in the atlas.
-            let start = outlines.len();
-            let end = start + spline.len();
+    /// Create an atlas from outlines saved with `save`. Fails with `InvalidData` if the file
+    /// was made from a different face or with a different conversion, or if it is corrupt.
+    pub fn load<P: AsRef<Path>>(face: &Face<'a>, path: P) -> io::Result<Atlas<'a>> {
+        let bytes = std::fs::read(path)?;
+        Atlas::parse(face, &bytes).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("truncated atlas file"),
+            _ => e,
+        })
+    }
 
-            outlines.extend(spline.strokes());
-            bboxes.push(*spline.bbox());
-            lut.push((start, end));
+    fn parse(face: &Face<'a>, mut r: &[u8]) -> io::Result<Atlas<'a>> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not an atlas file"));
+        }
+        if read_u32(&mut r)? != CONVERSION || read_u64(&mut r)? != font_hash(face) {
+            return Err(invalid("atlas file is for a different face or conversion"));
         }
 
-        Atlas {
-            outlines,
-            bboxes,
-            lut,
-            face,
+        let atlas = Atlas::new(face);
+        if read_u32(&mut r)? as usize != atlas.glyphs.len() {
+            return Err(invalid("wrong number of glyphs"));
+        }
+
+        for slot in &atlas.glyphs {
+            let bbox = Rect {
+                x0: read_f32(&mut r)?,
+                x1: read_f32(&mut r)?,
+                y0: read_f32(&mut r)?,
+                y1: read_f32(&mut r)?,
+            };
+            // Don't trust the count before allocating for it: A curve is 6 f32.
+            let n = read_u32(&mut r)? as usize;
+            if n > r.len() / 24 {
+                return Err(invalid("truncated atlas file"));
+            }
+            let mut beziers = Vec::with_capacity(n);
+            for _ in 0..n {
+                let mut p = [Point { x: 0.0, y: 0.0 }; 3];
+                for p in &mut p {
+                    p.x = read_f32(&mut r)?;
+                    p.y = read_f32(&mut r)?;
+                }
+                beziers.push(Quadratic(p[0], p[1], p[2]));
+            }
+            // Can't fail, the atlas is fresh.
+            let _ = slot.set(Spline::from_parts(beziers, bbox));
+        }
+
+        if !r.is_empty() {
+            return Err(invalid("trailing data in atlas file"));
+        }
+        Ok(atlas)
+    }
+
+    /// Save the outlines of all glyphs to a file, to be loaded with `load`. This outlines the
+    /// glyphs that have not been used yet.
+    ///
+    /// The format is little endian: The magic bytes `PLXA`, the conversion version (u32), the
+    /// hash of the face (u64) and the number of glyphs (u32), followed by each glyph as its
+    /// bounding box (4 f32), its number of curves (u32) and the curves (6 f32 each).
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.prefetch_all();
+
+        let mut w = BufWriter::new(std::fs::File::create(path)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&CONVERSION.to_le_bytes())?;
+        w.write_all(&self.font_hash().to_le_bytes())?;
+        w.write_all(&(self.glyphs.len() as u32).to_le_bytes())?;
+
+        for id in 0..self.glyphs.len() {
+            let spline = self.glyph(id);
+            let bbox = spline.bbox();
+            for v in [bbox.x0, bbox.x1, bbox.y0, bbox.y1] {
+                w.write_all(&v.to_le_bytes())?;
+            }
+            w.write_all(&(spline.len() as u32).to_le_bytes())?;
+            for q in spline.strokes() {
+                for p in [q.0, q.1, q.2] {
+                    w.write_all(&p.x.to_le_bytes())?;
+                    w.write_all(&p.y.to_le_bytes())?;
+                }
+            }
+        }
+
+        w.flush()
+    }
+
+    /// Load the atlas of a face from the cache directory `dir`, or build it and store it there
+    /// if it is not cached yet. The file name is derived from the face and the conversion, so
+    /// many faces can share a directory.
+    ///
+    /// The cache only saves time. If it can't be read or written, the atlas is built as usual.
+    pub fn cached<P: AsRef<Path>>(face: &Face<'a>, dir: P) -> Atlas<'a> {
+        let path = dir
+            .as_ref()
+            .join(format!("{:016x}-{}.atlas", font_hash(face), CONVERSION));
+
+        if let Ok(atlas) = Atlas::load(face, &path) {
+            return atlas;
+        }
+
+        let atlas = Atlas::new(face);
+        let _ = std::fs::create_dir_all(dir.as_ref()).and_then(|_| atlas.save(&path));
+        atlas
+    }
+
+    /// A hash of the font data and the variation coordinates the outlines are made from.
+    /// Atlases with the same hash have the same outlines.
+    pub fn font_hash(&self) -> u64 {
+        *self.hash.get_or_init(|| font_hash(&self.face))
+    }
+
+    /// The number of glyphs that have been outlined so far.
+    pub fn loaded(&self) -> usize {
+        self.glyphs.iter().filter(|g| g.get().is_some()).count()
+    }
+
+    /// Vertical metrics of the face, in units of 1em.
+    pub fn metrics(&self) -> FontMetrics {
+        FontMetrics::new(&self.face)
+    }
+
+    /// Measure a string set at `size`, without outlining or drawing it. This needs no GL
+    /// context, so plots can be laid out before anything is drawn.
+    pub fn measure(&self, text: &str, size: f32) -> Measurement {
+        self.measure_with(text, &ShapingOptions::default(), size)
+    }
+
+    pub fn measure_with(&self, text: &str, options: &ShapingOptions, size: f32) -> Measurement {
+        let glyphs = shaping::shape_with(text, &self.face, options);
+        let mut run = RunMetrics::new(&glyphs);
+
+        // The boxes in the font are those of the default instance. (See `assemble`)
+        if self.face.has_non_default_variation_coordinates() && !glyphs.is_empty() {
+            run.ink = self.outline_glyphs(text, &glyphs).bbox;
+        }
+
+        Measurement::new(&run, &self.metrics(), size)
+    }
+
+    pub fn outline(&self, text: &str) -> Outline {
+        self.outline_with(text, &ShapingOptions::default())
+    }
+
+    /// Outline a string, with control over the features, script, language and direction
+    /// used for shaping.
+    pub fn outline_with(&self, text: &str, options: &ShapingOptions) -> Outline {
+        let glyphs = shaping::shape_with(text, &self.face, options);
+        self.outline_glyphs(text, &glyphs)
+    }
+
+    /// Outline a string top to bottom, for vertical axis titles and East Asian text. The text
+    /// starts at the origin and goes down, centred on the y-axis. (See `shaping` for how the
+    /// glyphs are set.)
+    pub fn outline_vertical(&self, text: &str, options: &ShapingOptions) -> Outline {
+        let options = options.clone().direction(shaping::Direction::TopToBottom);
+        self.outline_with(text, &options)
+    }
+
+    /// Outline a string in a colour font. Each colour glyph is split into its layers, and the
+    /// layers are returned in the order they have to be painted. Consecutive layers of the same
+    /// colour are merged if they don't overlap, since overlapping parts of a layer cancel out
+    /// when it is filled. Glyphs without colour are merged like in `outline`, so a string
+    /// without colour glyphs comes out as a single layer in the colour of the text.
+    pub fn color_layers(&self, text: &str, palette: usize) -> Vec<ColorLayer> {
+        let glyphs = shaping::shape(text, &self.face);
+
+        // A run of glyphs of the same colour, and whether each of them is a plain glyph.
+        type Run = (Option<glm::Vec4>, Vec<(Glyph, bool)>);
+        let mut runs: Vec<Run> = Vec::new();
+        let ink = |g: &Glyph| Rect {
+            x0: g.bbox.x0 + g.x,
+            x1: g.bbox.x1 + g.x,
+            y0: g.bbox.y0 + g.y,
+            y1: g.bbox.y1 + g.y,
+        };
+
+        for g in glyphs {
+            let layers = color::layers(&self.face, g.glyph_id, palette);
+            let plain = layers.is_none();
+            let layers = layers.unwrap_or_else(|| {
+                vec![color::Layer {
+                    glyph_id: g.glyph_id,
+                    color: None,
+                }]
+            });
+
+            for layer in layers {
+                // The bounding box from shaping is that of the base glyph.
+                let spline = self.glyph(layer.glyph_id);
+                let bbox = if spline.len() > 0 { *spline.bbox() } else { g.bbox };
+                let glyph = Glyph {
+                    glyph_id: layer.glyph_id,
+                    bbox,
+                    ..g
+                };
+
+                let overlaps = |run: &[(Glyph, bool)]| {
+                    run.iter()
+                        .any(|(h, p)| !(plain && *p) && ink(h).intersects(&ink(&glyph)))
+                };
+                match runs.last_mut() {
+                    Some((color, run)) if *color == layer.color && !overlaps(run) => {
+                        run.push((glyph, plain))
+                    }
+                    _ => runs.push((layer.color, vec![(glyph, plain)])),
+                }
+            }
+        }
+
+        runs.into_iter()
+            .map(|(color, run)| {
+                let glyphs: Vec<Glyph> = run.into_iter().map(|(g, _)| g).collect();
+                ColorLayer {
+                    outline: self.outline_glyphs(text, &glyphs),
+                    color,
+                }
+            })
+            .collect()
+    }
+
+    /// Outline glyphs of `text` that were already shaped and positioned, for example by the
+    /// paragraph layout.
+    pub fn outline_glyphs(&self, text: &str, glyphs: &[Glyph]) -> Outline {
+        assemble(text, glyphs.iter().map(|g| (self, g)), &Synthesis::new())
+    }
+
+    /// Outline a string in a synthetic style, for faces without a bold or italic variant.
+    /// (See `style::Synthesis`)
+    pub fn outline_synthetic(
+        &self,
+        text: &str,
+        options: &ShapingOptions,
+        style: &Synthesis,
+    ) -> Outline {
+        let mut glyphs = shaping::shape_with(text, &self.face, options);
+        space(&mut glyphs, style);
+        assemble(text, glyphs.iter().map(|g| (self, g)), style)
+    }
+
+    /// Text with a stroke of `width` around it in another colour, to keep it legible over busy
+    /// plots. The stroke is the first layer, the text on top of it has the colour of the text.
+    pub fn halo(
+        &self,
+        text: &str,
+        options: &ShapingOptions,
+        style: &Synthesis,
+        width: f32,
+        color: glm::Vec4,
+    ) -> Vec<ColorLayer> {
+        let mut glyphs = shaping::shape_with(text, &self.face, options);
+        space(&mut glyphs, style);
+
+        // The stroke is the text emboldened by the width, and filled even if the text is only
+        // outlined. Emboldening moves the glyphs right by the extra amount, move them back.
+        let stroke = Synthesis {
+            embolden: style.embolden + width,
+            stroke: None,
+            ..*style
+        };
+        let mut outline = assemble(text, glyphs.iter().map(|g| (self, g)), &stroke);
+        for p in &mut outline.ctrl_pts {
+            p.0 -= width;
+        }
+        outline.bbox.x0 -= width;
+        outline.bbox.x1 -= width;
+        for span in &mut outline.glyphs {
+            span.bbox.x0 -= width;
+            span.bbox.x1 -= width;
+        }
+
+        vec![
+            ColorLayer {
+                outline,
+                color: Some(color),
+            },
+            ColorLayer {
+                outline: assemble(text, glyphs.iter().map(|g| (self, g)), style),
+                color: None,
+            },
+        ]
+    }
+}
+
+/// Make room for emboldened glyphs.
+fn space(glyphs: &mut [Glyph], style: &Synthesis) {
+    let extra = style.extra_advance();
+    for (i, g) in glyphs.iter_mut().enumerate() {
+        g.x += i as f32 * extra;
+        g.advance += extra;
+    }
+}
+
+/// Put the glyphs of `text` together into an outline, taking each glyph from its own atlas.
+fn assemble<'x, 'a: 'x>(
+    text: &str,
+    glyphs: impl Iterator<Item = (&'x Atlas<'a>, &'x Glyph)> + Clone,
+    style: &Synthesis,
+) -> Outline {
+    // A cluster extends to the start of the next one in the text (which need not be the next
+    // glyph, in right-to-left runs).
+    let mut starts: Vec<usize> = glyphs.clone().map(|(_, g)| g.cluster).collect();
+    starts.sort_unstable();
+    starts.dedup();
+    let cluster = |start: usize| {
+        let next = starts.partition_point(|&s| s <= start);
+        start..starts.get(next).copied().unwrap_or(text.len())
+    };
+
+    let mut vertices: Vec<(f32, f32)> = Vec::with_capacity(100);
+    let mut spans = Vec::with_capacity(starts.len());
+
+    let mut y0 = f32::INFINITY;
+    let mut y1 = -f32::INFINITY;
+    let mut x0 = f32::INFINITY;
+    let mut x1 = -f32::INFINITY;
+
+    for (atlas, g) in glyphs {
+        let styled;
+        let spline = if style.is_identity() {
+            atlas.glyph(g.glyph_id)
+        } else {
+            styled = style.apply(atlas.glyph(g.glyph_id));
+            &styled
+        };
+        let x = g.x; // offsets of the glyph
+        let y = g.y;
+        // The bounding boxes in the font are those of the default instance. Variations and
+        // synthetic styles can make glyphs larger, so use the box of the actual outline.
+        let restyled = atlas.face.has_non_default_variation_coordinates() || !style.is_identity();
+        let ink = if restyled && spline.len() > 0 {
+            let b = *spline.bbox();
+            match g.rotated {
+                true => Rect {
+                    x0: b.y0,
+                    x1: b.y1,
+                    y0: -b.x1,
+                    y1: -b.x0,
+                },
+                false => b,
+            }
+        } else {
+            g.bbox
+        };
+        let bbox = Rect {
+            x0: ink.x0 + x,
+            x1: ink.x1 + x,
+            y0: ink.y0 + y,
+            y1: ink.y1 + y,
+        };
+
+        // compute the accumulated bounding box
+        x0 = f32::min(x0, bbox.x0);
+        x1 = f32::max(x1, bbox.x1);
+        y0 = f32::min(y0, bbox.y0);
+        y1 = f32::max(y1, bbox.y1);
+
+        // Turned glyphs of vertical text. (See `shaping::Glyph::rotated`)
+        let turn = |p: Point| match g.rotated {
+            true => Point { x: p.y, y: -p.x },
+            false => p,
+        };
+
+        let first = vertices.len();
+        for curve in spline.strokes() {
+            vertices.push((turn(curve.0) + Point { x, y }).into());
+            vertices.push((turn(curve.1) + Point { x, y }).into());
+            vertices.push((turn(curve.2) + Point { x, y }).into());
+        }
+
+        spans.push(GlyphSpan {
+            glyph_id: g.glyph_id,
+            ctrl: first..vertices.len(),
+            cluster: cluster(g.cluster),
+            pen: (x, y),
+            advance: g.advance,
+            bbox,
+            vertical: g.vertical,
+        });
+    }
+
+    Outline {
+        ctrl_pts: vertices,
+        bbox: Rect { x0, x1, y0, y1 },
+        glyphs: spans,
+    }
+}
+
+/// A hash of the tables the outlines are made from, and the variation coordinates. This is
+/// FNV-1a rather than the hasher of the standard library, since it has to stay the same across
+/// builds.
+fn font_hash(face: &Face) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let tables = [*b"head", *b"maxp", *b"loca", *b"glyf", *b"CFF ", *b"CFF2", *b"gvar"];
+    let coords = face
+        .variation_coordinates()
+        .iter()
+        .flat_map(|c| c.get().to_le_bytes());
+
+    for tag in tables {
+        let data = face.table_data(ttf::Tag::from_bytes(&tag)).unwrap_or(&[]);
+        for &byte in tag.iter().chain(data) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
         }
     }
 
+    for byte in coords {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+fn read_f32(r: &mut impl Read) -> io::Result<f32> {
+    read_u32(r).map(f32::from_bits)
+}
+
+impl<'a> Fallback<'a> {
+    /// Create a fallback chain. The chain must contain at least one atlas.
+    pub fn new(atlases: Vec<Arc<Atlas<'a>>>) -> Self {
+        assert!(!atlases.is_empty(), "empty fallback chain");
+        Fallback { atlases }
+    }
+
     pub fn outline(&self, text: &str) -> Outline {
-        // this is a bit ugly atm. needs rework.
-        let glyphs = crate::shaping::shape(text, self.face);
-
-        let mut vertices: Vec<(f32, f32)> = Vec::with_capacity(100);
-
-        let mut y0 = f32::INFINITY;
-        let mut y1 = -f32::INFINITY;
-        let mut x0 = f32::INFINITY;
-        let mut x1 = -f32::INFINITY;
-
-        for g in &glyphs {
-            let (beg, end) = self.lut[g.glyph_id];
-            let x = g.x; // offsets of the glyph
-            let y = g.y;
-            let bbox = g.bbox;
-
-            // compute the accumulated bounding box
-            x0 = f32::min(x0, bbox.x0 + x);
-            x1 = f32::max(x1, bbox.x1 + x);
-            y0 = f32::min(y0, bbox.y0 + y);
-            y1 = f32::max(y1, bbox.y1 + y);
-
-            for i in beg..end {
-                let curve = self.outlines[i];
-                vertices.push((curve.0 + Point { x, y }).into());
-                vertices.push((curve.1 + Point { x, y }).into());
-                vertices.push((curve.2 + Point { x, y }).into());
+        self.outline_with(text, &ShapingOptions::default())
+    }
+
+    pub fn outline_with(&self, text: &str, options: &ShapingOptions) -> Outline {
+        // Shape the text, and figure out which atlas each glyph comes from.
+        let mut glyphs = Vec::with_capacity(text.len());
+        self.shape(text, options, 0, 0.0, 0, &mut glyphs);
+
+        assemble(
+            text,
+            glyphs
+                .iter()
+                .map(|(level, g)| (self.atlases[*level].as_ref(), g)),
+            &Synthesis::new(),
+        )
+    }
+
+    /// Shape `text` with the atlas at `level` in the chain, starting at the pen position `pen`
+    /// along the line, and re-shape any clusters that came out as .notdef with the next level.
+    /// The clusters of the resulting glyphs are relative to the full text, which starts
+    /// `offset` bytes before this slice of it. Returns the total advance.
+    fn shape(
+        &self,
+        text: &str,
+        options: &ShapingOptions,
+        level: usize,
+        pen: f32,
+        offset: usize,
+        out: &mut Vec<(usize, Glyph)>,
+    ) -> f32 {
+        // Note that feature ranges are not shifted to the slice. This is only a problem for
+        // features that don't cover the whole text, which the options builder never makes.
+        let glyphs = shaping::shape_with(text, &self.atlases[level].face, options);
+        let last_level = level + 1 == self.atlases.len();
+
+        // A cluster is missing if *any* of its glyphs is missing.
+        let missing: HashSet<usize> = glyphs
+            .iter()
+            .filter(|g| g.glyph_id == NOTDEF)
+            .map(|g| g.cluster)
+            .collect();
+        let mut clusters: Vec<usize> = glyphs.iter().map(|g| g.cluster).collect();
+        clusters.sort_unstable();
+        clusters.dedup();
+
+        // The glyphs keep their shaped positions (with the offsets of marks and kerning), moved
+        // by how much the fallback runs before them are longer than what they replace. The
+        // pen goes to the right, or down in vertical text.
+        let along = |g: Glyph, d: f32| match g.vertical {
+            true => Glyph { y: g.y - d, ..g },
+            false => Glyph { x: g.x + d, ..g },
+        };
+        let mut shift = pen;
+        let mut shaped = 0.0;
+        let mut i = 0;
+
+        while i < glyphs.len() {
+            let g = glyphs[i];
+
+            if last_level || !missing.contains(&g.cluster) {
+                out.push((
+                    level,
+                    Glyph {
+                        cluster: g.cluster + offset,
+                        ..along(g, shift)
+                    },
+                ));
+                shaped += g.advance;
+                i += 1;
+                continue;
+            }
+
+            // Find the run of missing clusters, and the byte range it covers. In right-to-left
+            // runs the clusters decrease, so the range goes from the smallest cluster to the
+            // end of the largest one.
+            let first = i;
+            while i < glyphs.len() && missing.contains(&glyphs[i].cluster) {
+                i += 1;
             }
+            let run = &glyphs[first..i];
+            let start = run.iter().map(|g| g.cluster).min().unwrap_or(0);
+            let last = run.iter().map(|g| g.cluster).max().unwrap_or(0);
+            let end = clusters
+                .get(clusters.partition_point(|&c| c <= last))
+                .copied()
+                .unwrap_or(text.len());
+
+            let replaced: f32 = run.iter().map(|g| g.advance).sum();
+            let advance = self.shape(
+                &text[start..end],
+                options,
+                level + 1,
+                shaped + shift,
+                offset + start,
+                out,
+            );
+            shaped += replaced;
+            shift += advance - replaced;
+        }
+
+        shaped + shift - pen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::{FontRegistry, Style, LATIN_MODERN_ROMAN, LM_MATH, LM_ROMAN};
+
+    /// A file in the temporary directory, removed when dropped.
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let name = format!("plox-{}-{}.atlas", std::process::id(), name);
+            TempFile(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let file = TempFile::new("roundtrip");
+        let atlas = Atlas::new(&LM_ROMAN);
+        atlas.save(&file.0).unwrap();
+
+        let loaded = Atlas::load(&LM_ROMAN, &file.0).unwrap();
+        assert_eq!(loaded.loaded(), loaded.glyphs.len());
+        let parts = |s: &Spline| {
+            let b = s.bbox();
+            let pts: Vec<(f32, f32)> = s
+                .strokes()
+                .flat_map(|q| [q.0, q.1, q.2])
+                .map(|p| (p.x, p.y))
+                .collect();
+            ((b.x0, b.x1, b.y0, b.y1), pts)
+        };
+        for id in 0..atlas.glyphs.len() {
+            assert_eq!(parts(atlas.glyph(id)), parts(loaded.glyph(id)));
         }
+    }
+
+    #[test]
+    fn load_corrupt_file() {
+        let file = TempFile::new("corrupt");
+        Atlas::new(&LM_ROMAN).save(&file.0).unwrap();
+        let good = std::fs::read(&file.0).unwrap();
+
+        let load = |bytes: &[u8]| {
+            std::fs::write(&file.0, bytes).unwrap();
+            Atlas::load(&LM_ROMAN, &file.0).map(|_| ())
+        };
 
-        Outline {
-            ctrl_pts: vertices,
-            bbox: Rect { x0, x1, y0, y1 },
+        // The curve count of the first glyph, after the header and its bounding box.
+        let count = 4 + 4 + 8 + 4 + 16;
+        let mut huge = good.clone();
+        huge[count..count + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let mut magic = good.clone();
+        magic[0] = b'X';
+
+        let mut longer = good.clone();
+        longer.push(0);
+
+        for bytes in [
+            &good[..good.len() - 1],
+            &good[..count + 2],
+            &good[..3],
+            &[],
+            &huge,
+            &magic,
+            &longer,
+        ] {
+            let err = load(bytes).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", err);
         }
+
+        // Atlases of other faces are rejected too.
+        std::fs::write(&file.0, &good).unwrap();
+        let err = Atlas::load(&LM_MATH, &file.0).map(|_| ()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(load(&good).is_ok());
+    }
+
+    #[test]
+    fn fallback_keeps_shaped_positions() {
+        let roman = Arc::new(Atlas::new(&LM_ROMAN));
+        let math = Arc::new(Atlas::new(&LM_MATH));
+        assert!(roman.face.glyph_index('∫').is_none());
+        let fallback = Fallback::new(vec![roman.clone(), math.clone()]);
+
+        // Kerning and a combining mark before the missing glyph, kerning after it.
+        let before = "AVq\u{301}";
+        let after = "AV";
+        let outline = fallback.outline(&format!("{}∫{}", before, after));
+
+        let shaped = shaping::shape(before, &roman.face);
+        for (span, g) in outline.glyphs.iter().zip(&shaped) {
+            assert_eq!(span.glyph_id, g.glyph_id);
+            assert_eq!(span.pen, (g.x, g.y));
+        }
+
+        // The integral comes from the math face, right after the text before it.
+        let integral = &outline.glyphs[shaped.len()];
+        let width: f32 = shaped.iter().map(|g| g.advance).sum();
+        assert_ne!(integral.glyph_id, NOTDEF);
+        assert_eq!(integral.pen.0, width);
+
+        // The text after it is moved by the advance of the integral, and keeps its kerning.
+        let rest = &outline.glyphs[shaped.len() + 1..];
+        let x = width + integral.advance;
+        let shaped = shaping::shape(after, &roman.face);
+        assert_eq!(rest.len(), shaped.len());
+        for (span, g) in rest.iter().zip(&shaped) {
+            assert!((span.pen.0 - (x + g.x)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn vary_a_shared_atlas() {
+        let mut registry = FontRegistry::latin_modern();
+        let old = registry.atlas(LATIN_MODERN_ROMAN, Style::Regular).unwrap();
+        old.outline("Wave");
+
+        let new = registry
+            .set_variations(LATIN_MODERN_ROMAN, Style::Regular, &[variation::weight(700.0)])
+            .unwrap();
+        assert!(!Arc::ptr_eq(&old, &new));
+        assert_eq!(new.loaded(), old.loaded());
+
+        // The registry hands out the new atlas from now on.
+        let again = registry.atlas(LATIN_MODERN_ROMAN, Style::Regular).unwrap();
+        assert!(Arc::ptr_eq(&new, &again));
+    }
+
+    #[test]
+    fn plain_text_is_one_color_layer() {
+        // Kerning pulls the boxes of "AV" into each other, which is fine for plain glyphs.
+        let atlas = Atlas::new(&LM_ROMAN);
+        let layers = atlas.color_layers("AVAV", 0);
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].outline.glyphs.len(), 4);
+        assert!(layers[0].color.is_none());
+    }
+
+    #[test]
+    fn fallback_in_vertical_text() {
+        let roman = Arc::new(Atlas::new(&LM_ROMAN));
+        let math = Arc::new(Atlas::new(&LM_MATH));
+        let fallback = Fallback::new(vec![roman.clone(), math.clone()]);
+        let options = ShapingOptions::new().direction(shaping::Direction::TopToBottom);
+
+        // The turned glyphs sit beside the line x = 0, so their x is not a pen position.
+        let outline = fallback.outline_with("AV∫AV", &options);
+        let shaped = shaping::shape_with("AV", &roman.face, &options);
+        let integral = shaping::shape_with("∫", &math.face, &options)[0];
+        assert_eq!(outline.glyphs.len(), 5);
+
+        // Going down: "AV", the integral below it, and "AV" below that.
+        let height: f32 = shaped.iter().map(|g| g.advance).sum();
+        let pens: Vec<(f32, f32)> = shaped
+            .iter()
+            .map(|g| (g.x, g.y))
+            .chain([(integral.x, integral.y - height)])
+            .chain(
+                shaped
+                    .iter()
+                    .map(|g| (g.x, g.y - height - integral.advance)),
+            )
+            .collect();
+        for (span, pen) in outline.glyphs.iter().zip(pens) {
+            assert!(span.vertical);
+            assert!((span.pen.0 - pen.0).abs() < 1e-5 && (span.pen.1 - pen.1).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn measure_matches_the_outline() {
+        let atlas = Atlas::new(&LM_ROMAN);
+        let size = 2.0;
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+        let check = |m: Measurement, outline: Outline| {
+            let advance: f32 = outline.glyphs.iter().map(|g| g.advance).sum();
+            let b = outline.bbox;
+            assert!(close(m.advance, size * advance));
+            assert!(close(m.ink.x0, size * b.x0) && close(m.ink.x1, size * b.x1));
+            assert!(close(m.ink.y0, size * b.y0) && close(m.ink.y1, size * b.y1));
+        };
+
+        let m = atlas.measure("Measure gy", size);
+        check(m, atlas.outline("Measure gy"));
+
+        // The line box is the advance, and the face from the descender to the ascender.
+        let font = atlas.metrics();
+        assert!(close(m.baseline, size * font.ascender));
+        assert!(close(m.line.y1, size * font.ascender));
+        assert!(close(m.line.y0, size * font.descender));
+        assert!(m.line.x0 == 0.0 && close(m.line.x1, m.advance));
+        assert!(m.ink.y0 < 0.0 && m.line.y0 < m.ink.y0);
+
+        // Vertical text goes down from the origin.
+        let options = ShapingOptions::new().direction(shaping::Direction::TopToBottom);
+        let m = atlas.measure_with("Vert", &options, size);
+        check(m, atlas.outline_vertical("Vert", &ShapingOptions::new()));
+        assert!(m.ink.y1 <= 0.0 && m.ink.y0 < -3.0);
     }
 }
@@ -0,0 +1,250 @@
+//! Caching of shaped text.
+//!
+//! Labels are typically re-set every frame even if the text did not change, and a lot of
+//! labels (tick labels, for example) share their text. Shaping and outlining is by far the most
+//! expensive part of updating a text element, so it pays off to remember the results.
+use crate::atlas::{Atlas, Outline};
+use crate::shaping::{self, Glyph, ShapingOptions};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// A least-recently-used cache with a fixed capacity.
+///
+/// Eviction scans the whole cache for the oldest entry, which is fine for the few hundred
+/// entries we need, and a lot simpler than maintaining a linked list.
+pub struct Lru<K, V> {
+    entries: HashMap<K, (V, u64)>,
+    capacity: usize,
+    // Monotonic clock to time stamp the entries with.
+    clock: u64,
+}
+
+/// What a cached result was made from: Which font, how it was shaped, and what text.
+/// Fonts are identified by `Atlas::font_hash`, a hash of their data and the coordinates of
+/// variable fonts, so a font that is loaded again (or at the address of one that was dropped)
+/// is the same font if and only if it has the same outlines.
+#[derive(Debug, Clone)]
+struct Key {
+    font: u64,
+    options: ShapingOptions,
+    text: String,
+}
+
+/// The caches are indexed by the hash of the key, so looking something up does not have to
+/// allocate a key. The full key is stored alongside the value to catch hash collisions.
+type Entry<V> = (Arc<Key>, V);
+
+/// Caches both shaped glyph runs and outlines.
+pub struct TextCache {
+    glyphs: Lru<u64, Entry<Arc<Vec<Glyph>>>>,
+    outlines: Lru<u64, Entry<Arc<Outline>>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Lru<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "cache must have room for at least one entry");
+        Lru {
+            entries: HashMap::with_capacity(capacity),
+            capacity,
+            clock: 0,
+        }
+    }
+
+    /// Look up an entry, and mark it as recently used.
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|(v, t)| {
+            *t = clock;
+            v.clone()
+        })
+    }
+
+    /// Insert an entry, evicting the least recently used one if the cache is full.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.clock += 1;
+
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, t))| *t)
+                .map(|(k, _)| k.clone());
+
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(key, (value, self.clock));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl TextCache {
+    /// A cache with room for `capacity` shaped runs and `capacity` outlines.
+    pub fn new(capacity: usize) -> Self {
+        TextCache {
+            glyphs: Lru::new(capacity),
+            outlines: Lru::new(capacity),
+        }
+    }
+
+    /// Shape a string with the face of an atlas, or get the result of shaping it earlier.
+    pub fn shape(
+        &mut self,
+        text: &str,
+        atlas: &Atlas,
+        options: &ShapingOptions,
+    ) -> Arc<Vec<Glyph>> {
+        let font = atlas.font_hash();
+        let hash = Key::hash(text, font, options);
+
+        if let Some((key, glyphs)) = self.glyphs.get(&hash) {
+            if key.matches(text, font, options) {
+                return glyphs;
+            }
+        }
+
+        let glyphs = Arc::new(shaping::shape_with(text, &atlas.face, options));
+        let key = Arc::new(Key::new(text, font, options));
+        self.glyphs.insert(hash, (key, glyphs.clone()));
+        glyphs
+    }
+
+    /// Outline a string in the given atlas, or get the outline made earlier. The shaping is
+    /// cached as well, so outlines that were evicted don't have to be shaped again.
+    pub fn outline(&mut self, text: &str, atlas: &Atlas, options: &ShapingOptions) -> Arc<Outline> {
+        let font = atlas.font_hash();
+        let hash = Key::hash(text, font, options);
+
+        if let Some((key, outline)) = self.outlines.get(&hash) {
+            if key.matches(text, font, options) {
+                return outline;
+            }
+        }
+
+        let glyphs = self.shape(text, atlas, options);
+        let outline = Arc::new(atlas.outline_glyphs(text, &glyphs));
+        let key = Arc::new(Key::new(text, font, options));
+        self.outlines.insert(hash, (key, outline.clone()));
+        outline
+    }
+
+    pub fn clear(&mut self) {
+        self.glyphs.clear();
+        self.outlines.clear();
+    }
+}
+
+impl Default for TextCache {
+    fn default() -> Self {
+        TextCache::new(256)
+    }
+}
+
+impl Key {
+    fn new(text: &str, font: u64, options: &ShapingOptions) -> Self {
+        Key {
+            font,
+            options: options.clone(),
+            text: text.to_string(),
+        }
+    }
+
+    fn hash(text: &str, font: u64, options: &ShapingOptions) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        font.hash(&mut hasher);
+        options.hash(&mut hasher);
+        text.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn matches(&self, text: &str, font: u64, options: &ShapingOptions) -> bool {
+        self.font == font && self.options == *options && self.text == text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::{LM_ROMAN, LM_ROMAN_ITALIC};
+
+    #[test]
+    fn get_makes_an_entry_recent() {
+        let mut lru = Lru::new(2);
+        lru.insert("a", 1);
+        lru.insert("b", 2);
+        assert_eq!(lru.get(&"a"), Some(1));
+
+        // "b" is the least recently used now.
+        lru.insert("c", 3);
+        assert_eq!(lru.len(), 2);
+        assert_eq!(lru.get(&"b"), None);
+        assert_eq!(lru.get(&"a"), Some(1));
+        assert_eq!(lru.get(&"c"), Some(3));
+    }
+
+    #[test]
+    fn hits_are_shared() {
+        let atlas = Atlas::new(&LM_ROMAN);
+        let options = ShapingOptions::default();
+        let mut cache = TextCache::new(8);
+
+        let outline = cache.outline("label", &atlas, &options);
+        let again = cache.outline("label", &atlas, &options);
+        let other = cache.outline("other", &atlas, &options);
+        assert!(Arc::ptr_eq(&outline, &again));
+        assert!(!Arc::ptr_eq(&outline, &other));
+
+        // The outline was shaped through the cache.
+        let glyphs = cache.shape("label", &atlas, &options);
+        let again = cache.shape("label", &atlas, &options);
+        assert_eq!(glyphs.len(), outline.glyphs.len());
+        assert!(Arc::ptr_eq(&glyphs, &again));
+    }
+
+    #[test]
+    fn keys_are_compared_in_full() {
+        let roman = Atlas::new(&LM_ROMAN);
+        let italic = Atlas::new(&LM_ROMAN_ITALIC);
+        let options = ShapingOptions::default();
+        let mut cache = TextCache::new(8);
+
+        // Pretend the italic "text" collides with the roman one.
+        let roman_text = cache.outline("text", &roman, &options);
+        let hash = Key::hash("text", italic.font_hash(), &options);
+        let key = Arc::new(Key::new("text", roman.font_hash(), &options));
+        cache.outlines.insert(hash, (key, roman_text.clone()));
+
+        let italic_text = cache.outline("text", &italic, &options);
+        assert!(!Arc::ptr_eq(&roman_text, &italic_text));
+        assert_eq!(*italic_text.ctrl_pts, italic.outline("text").ctrl_pts);
+    }
+
+    #[test]
+    fn copies_of_a_font_are_the_same_font() {
+        // At another address.
+        let data = include_bytes!("../res/lm/lmroman10-regular.otf").to_vec();
+        let copy = rustybuzz::Face::from_slice(&data, 0).unwrap();
+        let options = ShapingOptions::default();
+        let mut cache = TextCache::new(8);
+
+        let a = cache.outline("text", &Atlas::new(&LM_ROMAN), &options);
+        let b = cache.outline("text", &Atlas::new(&copy), &options);
+        assert!(Arc::ptr_eq(&a, &b));
+    }
+}
@@ -0,0 +1,184 @@
+//! Colour fonts.
+//!
+//! A glyph in a COLR (version 0) font is a stack of other glyphs, each filled with a colour
+//! from a palette in the CPAL table. Emoji and icon fonts use this, and it fits our pipeline
+//! well: Every layer is an ordinary outline.
+//!
+//! ttf-parser does not read these tables, so they are parsed here.
+//! (https://learn.microsoft.com/en-us/typography/opentype/spec/colr)
+use rustybuzz::Face;
+
+/// A layer of a colour glyph.
+#[derive(Debug, Clone, Copy)]
+pub struct Layer {
+    pub glyph_id: usize,
+    /// The colour of the layer (non-premultiplied sRGB), or `None` if it should have the
+    /// colour of the text.
+    pub color: Option<glm::Vec4>,
+}
+
+/// The layers of a glyph, bottom first. `None` if the glyph is not a colour glyph.
+/// Palette indices that are out of range fall back to the first palette.
+pub fn layers(face: &Face, glyph_id: usize, palette: usize) -> Option<Vec<Layer>> {
+    let colr = face.table_data(ttf::Tag::from_bytes(b"COLR"))?;
+    let cpal = face.table_data(ttf::Tag::from_bytes(b"CPAL"));
+    parse_layers(colr, cpal, glyph_id, palette)
+}
+
+/// `layers`, from the COLR and CPAL tables.
+fn parse_layers(
+    colr: &[u8],
+    cpal: Option<&[u8]>,
+    glyph_id: usize,
+    palette: usize,
+) -> Option<Vec<Layer>> {
+    let n_bases = u16_at(colr, 2)? as usize;
+    let bases = u32_at(colr, 4)? as usize;
+    let layers = u32_at(colr, 8)? as usize;
+
+    // The base glyph records are sorted by glyph id.
+    let id = |i: usize| u16_at(colr, bases + 6 * i).map(|id| id as usize);
+    let mut lo = 0;
+    let mut hi = n_bases;
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if id(mid)? < glyph_id {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    if lo == n_bases || id(lo)? != glyph_id {
+        return None;
+    }
+
+    let first = u16_at(colr, bases + 6 * lo + 2)? as usize;
+    let count = u16_at(colr, bases + 6 * lo + 4)? as usize;
+
+    (first..first + count)
+        .map(|i| {
+            let record = layers + 4 * i;
+            let glyph_id = u16_at(colr, record)? as usize;
+            let index = u16_at(colr, record + 2)?;
+            let color = match index {
+                0xFFFF => None,
+                index => cpal.and_then(|cpal| color(cpal, palette, index as usize)),
+            };
+            Some(Layer { glyph_id, color })
+        })
+        .collect()
+}
+
+/// The number of palettes of a colour font.
+pub fn palettes(face: &Face) -> usize {
+    face.table_data(ttf::Tag::from_bytes(b"CPAL"))
+        .and_then(|cpal| u16_at(cpal, 4))
+        .unwrap_or(0) as usize
+}
+
+/// Colour `index` of a palette, from the CPAL table.
+fn color(cpal: &[u8], palette: usize, index: usize) -> Option<glm::Vec4> {
+    let n_entries = u16_at(cpal, 2)? as usize;
+    let n_palettes = u16_at(cpal, 4)? as usize;
+    let records = u32_at(cpal, 8)? as usize;
+
+    let palette = if palette < n_palettes { palette } else { 0 };
+    if index >= n_entries {
+        return None;
+    }
+
+    let first = u16_at(cpal, 12 + 2 * palette)? as usize;
+    let bgra = cpal.get(records + 4 * (first + index)..records + 4 * (first + index) + 4)?;
+    let c = |b: u8| b as f32 / 255.0;
+
+    Some(glm::vec4(c(bgra[2]), c(bgra[1]), c(bgra[0]), c(bgra[3])))
+}
+
+fn u16_at(data: &[u8], i: usize) -> Option<u16> {
+    data.get(i..i + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn u32_at(data: &[u8], i: usize) -> Option<u32> {
+    data.get(i..i + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be16(v: &[u16]) -> Vec<u8> {
+        v.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    /// Base glyphs 3, 7 and 10, with two, one and two layers.
+    fn colr() -> Vec<u8> {
+        let bases = [[3, 0, 2], [7, 2, 1], [10, 3, 2]];
+        let layers = [[20, 0], [21, 1], [22, 0xFFFF], [23, 2], [24, 3]];
+
+        let mut colr = be16(&[0, bases.len() as u16]);
+        colr.extend(14u32.to_be_bytes());
+        colr.extend((14 + 6 * bases.len() as u32).to_be_bytes());
+        colr.extend(be16(&[layers.len() as u16]));
+        colr.extend(bases.iter().flat_map(|b| be16(b)));
+        colr.extend(layers.iter().flat_map(|l| be16(l)));
+        colr
+    }
+
+    /// Two palettes of three colours, as BGRA.
+    fn cpal() -> Vec<u8> {
+        let mut cpal = be16(&[0, 3, 2, 6]);
+        cpal.extend(16u32.to_be_bytes());
+        cpal.extend(be16(&[0, 3]));
+        cpal.extend([0, 0, 255, 255, 0, 255, 0, 255, 255, 0, 0, 51]);
+        cpal.extend([255, 255, 255, 255, 0, 0, 0, 255, 0, 255, 255, 255]);
+        cpal
+    }
+
+    fn ids(layers: &[Layer]) -> Vec<usize> {
+        layers.iter().map(|l| l.glyph_id).collect()
+    }
+
+    #[test]
+    fn layers_of_base_glyphs() {
+        let (colr, cpal) = (colr(), cpal());
+        let layers = |id| parse_layers(&colr, Some(&cpal), id, 0);
+
+        assert_eq!(ids(&layers(3).unwrap()), [20, 21]);
+        assert_eq!(ids(&layers(7).unwrap()), [22]);
+        assert_eq!(ids(&layers(10).unwrap()), [23, 24]);
+        for id in [0, 4, 8, 11, 1000] {
+            assert!(layers(id).is_none());
+        }
+
+        // Truncated tables are no colour glyphs, rather than a panic.
+        assert!(parse_layers(&colr[..20], Some(&cpal), 10, 0).is_none());
+        assert!(parse_layers(&colr[..30], Some(&cpal), 10, 0).is_none());
+    }
+
+    #[test]
+    fn layer_colors() {
+        let (colr, cpal) = (colr(), cpal());
+        let colors = |id, palette| {
+            let layers = parse_layers(&colr, Some(&cpal), id, palette).unwrap();
+            layers.iter().map(|l| l.color).collect::<Vec<_>>()
+        };
+        let rgba = |r, g, b, a| Some(glm::vec4(r, g, b, a));
+        let (red, green) = (rgba(1.0, 0.0, 0.0, 1.0), rgba(0.0, 1.0, 0.0, 1.0));
+        let (white, black) = (rgba(1.0, 1.0, 1.0, 1.0), rgba(0.0, 0.0, 0.0, 1.0));
+
+        // BGRA in the table.
+        assert_eq!(colors(3, 0), [red, green]);
+        // 0xFFFF is the colour of the text, and entries past the palette have none either.
+        assert_eq!(colors(7, 0), [None]);
+        assert_eq!(colors(10, 0), [rgba(0.0, 0.0, 1.0, 0.2), None]);
+
+        // The second palette, and the first for palettes that don't exist.
+        assert_eq!(colors(3, 1), [white, black]);
+        assert_eq!(colors(3, 2), colors(3, 0));
+
+        // Without a palette, every layer has the colour of the text.
+        let layers = parse_layers(&colr, None, 3, 0).unwrap();
+        assert!(layers.iter().all(|l| l.color.is_none()));
+    }
+}
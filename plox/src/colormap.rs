@@ -0,0 +1,40 @@
+//! Colormaps.
+//!
+//! The colormaps are stored as a handful of evenly spaced stops. This is plenty, since the GPU
+//! interpolates linearly between them anyways. (See `gpu::Texture::colormap`)
+use glm::Vec4;
+
+/// Matplotlibs default colormap. Perceptually uniform, and readable when printed in grayscale.
+pub const VIRIDIS: [[f32; 3]; 9] = [
+    [0.267, 0.005, 0.329],
+    [0.283, 0.141, 0.458],
+    [0.254, 0.265, 0.530],
+    [0.192, 0.407, 0.556],
+    [0.150, 0.510, 0.557],
+    [0.122, 0.620, 0.537],
+    [0.208, 0.718, 0.473],
+    [0.431, 0.808, 0.345],
+    [0.993, 0.906, 0.144],
+];
+
+/// Opaque colours of a colormap, ready to upload as a texture.
+pub fn colors(map: &[[f32; 3]]) -> Vec<Vec4> {
+    map.iter()
+        .map(|&[r, g, b]| glm::vec4(r, g, b, 1.0))
+        .collect()
+}
+
+/// Look up t ∈ [0, 1] in a colormap on the CPU. Useful for per-vertex colours.
+pub fn sample(map: &[[f32; 3]], t: f32) -> Vec4 {
+    let s = t.clamp(0.0, 1.0) * (map.len() - 1) as f32;
+    let i = usize::min(s.floor() as usize, map.len() - 2);
+    let t = s - i as f32;
+    let [r0, g0, b0] = map[i];
+    let [r1, g1, b1] = map[i + 1];
+    glm::vec4(
+        (1.0 - t) * r0 + t * r1,
+        (1.0 - t) * g0 + t * g1,
+        (1.0 - t) * b0 + t * b1,
+        1.0,
+    )
+}
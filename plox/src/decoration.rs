@@ -0,0 +1,391 @@
+//! Text decorations.
+//!
+//! Underlines, strikethroughs and overlines are added to an outline as rectangles, so they
+//! rasterize in the same pass as the glyphs. The XOR fill needs some care: Where a rectangle
+//! covers ink, the two would cancel out. So for every rectangle, the part of the glyphs it
+//! covers is added once more, and glyph XOR rectangle XOR (glyph AND rectangle) is the union
+//! of the two.
+use crate::atlas::Outline;
+use crate::metrics::{FontMetrics, LineMetrics};
+use crate::spline::Rect;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoration {
+    Underline,
+    Strikethrough,
+    Overline,
+}
+
+type Curve = [[f32; 2]; 3];
+
+impl Decoration {
+    /// Where the line goes, from the metrics of the font.
+    pub fn metrics(&self, font: &FontMetrics) -> LineMetrics {
+        match self {
+            Decoration::Underline => font.underline,
+            Decoration::Strikethrough => font.strikeout,
+            // Fonts have no metrics for this. Put it just below the ascender, so it stays
+            // within the box of the line.
+            Decoration::Overline => LineMetrics {
+                position: font.ascender - 0.5 * font.underline.thickness,
+                thickness: font.underline.thickness,
+            },
+        }
+    }
+}
+
+/// A line of text, for decorating it.
+struct Line {
+    x0: f32,
+    x1: f32,
+    /// The heights of the baselines on the line, and how much of the text sits on each.
+    baselines: Vec<(f32, f32)>,
+}
+
+impl Line {
+    /// The baseline most of the text sits on, so super- and subscripts are decorated at the
+    /// height of the rest of the line.
+    fn baseline(&self) -> f32 {
+        self.baselines
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(0.0, |b| b.0)
+    }
+}
+
+/// Add a decoration to every line of an outline of text. With `skip_ink`, underlines and
+/// overlines are interrupted where they would touch the glyphs, like in browsers. Lines are
+/// never interrupted by strikethroughs.
+///
+/// The lines run from the pen position of the first glyph on a line to the advance of the
+/// last, so they include the spacing of the glyphs. Glyphs within half a line height of each
+/// other are on the same line, and the decoration goes along the baseline most of the text
+/// is on.
+///
+/// Panics if the text is vertical, there are no decorations for vertical text.
+pub fn decorate(outline: &mut Outline, font: &FontMetrics, decoration: Decoration, skip_ink: bool) {
+    assert!(
+        outline.glyphs.iter().all(|g| !g.vertical),
+        "vertical text can't be decorated"
+    );
+    for rect in rects(outline, font, decoration, skip_ink) {
+        let covered: Vec<Curve> = outline
+            .glyphs
+            .iter()
+            .filter(|g| g.bbox.intersects(&rect))
+            .flat_map(|g| {
+                let curves = curves(&outline.ctrl_pts[g.ctrl.clone()]);
+                let curves = clip(&curves, 1, rect.y0, rect.y1);
+                clip(&curves, 0, rect.x0, rect.x1)
+            })
+            .collect();
+
+        let corners = [
+            [rect.x0, rect.y0],
+            [rect.x1, rect.y0],
+            [rect.x1, rect.y1],
+            [rect.x0, rect.y1],
+        ];
+        for i in 0..4 {
+            let a = corners[i];
+            let b = corners[(i + 1) % 4];
+            let mid = [0.5 * (a[0] + b[0]), 0.5 * (a[1] + b[1])];
+            outline.ctrl_pts.extend([a, mid, b].map(|p| (p[0], p[1])));
+        }
+        for curve in covered {
+            outline.ctrl_pts.extend(curve.map(|p| (p[0], p[1])));
+        }
+
+        outline.bbox = outline.bbox.extend(rect);
+    }
+}
+
+/// The rectangles of a decoration on every line of an outline. (See `decorate`)
+fn rects(
+    outline: &Outline,
+    font: &FontMetrics,
+    decoration: Decoration,
+    skip_ink: bool,
+) -> Vec<Rect> {
+    let line = decoration.metrics(font);
+    let skip_ink = skip_ink && decoration != Decoration::Strikethrough;
+    let tolerance = 0.5 * font.line_height();
+
+    let mut lines: Vec<Line> = Vec::new();
+    for g in &outline.glyphs {
+        let (x, y) = g.pen;
+        let (x0, x1) = (x.min(x + g.advance), x.max(x + g.advance));
+        let on_line = |l: &&mut Line| l.baselines.iter().any(|b| (b.0 - y).abs() < tolerance);
+        match lines.iter_mut().find(on_line) {
+            Some(l) => {
+                l.x0 = l.x0.min(x0);
+                l.x1 = l.x1.max(x1);
+                match l.baselines.iter_mut().find(|b| b.0 == y) {
+                    Some(b) => b.1 += g.advance.abs(),
+                    None => l.baselines.push((y, g.advance.abs())),
+                }
+            }
+            None => lines.push(Line {
+                x0,
+                x1,
+                baselines: vec![(y, g.advance.abs())],
+            }),
+        }
+    }
+
+    let mut rects = Vec::new();
+    for l in lines {
+        let (baseline, x0, x1) = (l.baseline(), l.x0, l.x1);
+        let y0 = baseline + line.position - 0.5 * line.thickness;
+        let y1 = baseline + line.position + 0.5 * line.thickness;
+
+        let mut pieces = vec![(x0, x1)];
+        if skip_ink {
+            // Keep a gap of the thickness of the line to the ink.
+            let pad = line.thickness;
+            for (g0, g1) in ink(outline, y0 - pad, y1 + pad) {
+                pieces = pieces
+                    .into_iter()
+                    .flat_map(|(a, b)| [(a, b.min(g0 - pad)), (a.max(g1 + pad), b)])
+                    .filter(|(a, b)| b > a)
+                    .collect();
+            }
+        }
+
+        rects.extend(pieces.into_iter().map(|(x0, x1)| Rect { x0, x1, y0, y1 }));
+    }
+
+    rects
+}
+
+/// The horizontal extents of the ink of each glyph between two heights.
+fn ink(outline: &Outline, y0: f32, y1: f32) -> Vec<(f32, f32)> {
+    let band = Rect {
+        x0: -f32::INFINITY,
+        x1: f32::INFINITY,
+        y0,
+        y1,
+    };
+
+    outline
+        .glyphs
+        .iter()
+        .filter(|g| g.bbox.intersects(&band))
+        .filter_map(|g| {
+            let curves = curves(&outline.ctrl_pts[g.ctrl.clone()]);
+            clip(&curves, 1, y0, y1)
+                .into_iter()
+                // Curves that were flattened onto the edges of the band are not ink.
+                .filter(|c| !c.iter().all(|p| p[1] == y0) && !c.iter().all(|p| p[1] == y1))
+                .flatten()
+                .map(|p| (p[0], p[0]))
+                .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)))
+        })
+        .collect()
+}
+
+fn curves(ctrl_pts: &[(f32, f32)]) -> Vec<Curve> {
+    ctrl_pts
+        .chunks_exact(3)
+        .map(|c| [[c[0].0, c[0].1], [c[1].0, c[1].1], [c[2].0, c[2].1]])
+        .collect()
+}
+
+/// Clip closed contours to the slab `lo..hi` along an axis. The curves are split where they
+/// cross the edges of the slab, and the pieces outside are flattened onto the edges. This
+/// keeps the contours closed, and the flattened pieces enclose no area, so the XOR fill of
+/// the result is exactly the part of the original within the slab.
+fn clip(curves: &[Curve], axis: usize, lo: f32, hi: f32) -> Vec<Curve> {
+    let mut out = Vec::with_capacity(curves.len());
+
+    let mut push = |mut piece: Curve| {
+        let mid = 0.25 * (piece[0][axis] + 2.0 * piece[1][axis] + piece[2][axis]);
+        let edge = if mid < lo {
+            Some(lo)
+        } else if mid > hi {
+            Some(hi)
+        } else {
+            None
+        };
+        if let Some(edge) = edge {
+            for p in &mut piece {
+                p[axis] = edge;
+            }
+        }
+        out.push(piece);
+    };
+
+    for curve in curves {
+        let mut ts: Vec<f32> = crossings(curve, axis, lo);
+        ts.extend(crossings(curve, axis, hi));
+        ts.sort_by(f32::total_cmp);
+
+        let mut rest = *curve;
+        let mut t0 = 0.0;
+        for t in ts {
+            let (head, tail) = split(&rest, (t - t0) / (1.0 - t0));
+            push(head);
+            rest = tail;
+            t0 = t;
+        }
+        push(rest);
+    }
+
+    out
+}
+
+/// Parameters in (0, 1) where a curve crosses `value` along an axis.
+fn crossings(curve: &Curve, axis: usize, value: f32) -> Vec<f32> {
+    let (p0, p1, p2) = (curve[0][axis], curve[1][axis], curve[2][axis]);
+    let a = p0 - 2.0 * p1 + p2;
+    let b = 2.0 * (p1 - p0);
+    let c = p0 - value;
+
+    let roots = if a.abs() < 1e-9 {
+        if b.abs() < 1e-9 {
+            vec![]
+        } else {
+            vec![-c / b]
+        }
+    } else {
+        let d = b * b - 4.0 * a * c;
+        if d < 0.0 {
+            vec![]
+        } else {
+            let d = d.sqrt();
+            vec![(-b - d) / (2.0 * a), (-b + d) / (2.0 * a)]
+        }
+    };
+
+    roots
+        .into_iter()
+        .filter(|t| *t > 1e-6 && *t < 1.0 - 1e-6)
+        .collect()
+}
+
+/// Split a curve at `t` (de Casteljau).
+fn split(curve: &Curve, t: f32) -> (Curve, Curve) {
+    let lerp = |a: [f32; 2], b: [f32; 2]| [a[0] + t * (b[0] - a[0]), a[1] + t * (b[1] - a[1])];
+    let [p0, p1, p2] = *curve;
+    let a = lerp(p0, p1);
+    let b = lerp(p1, p2);
+    let m = lerp(a, b);
+    ([p0, a, m], [m, b, p2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atlas::Atlas;
+    use crate::font::LM_ROMAN;
+    use crate::paragraph::Paragraph;
+    use crate::rich::{RichText, Span};
+    use crate::shaping::ShapingOptions;
+
+    /// The area enclosed by closed contours of quadratic curves.
+    fn area(curves: &[Curve]) -> f32 {
+        let cross = |a: [f32; 2], b: [f32; 2]| a[0] * b[1] - a[1] * b[0];
+        curves
+            .iter()
+            .map(|[p0, p1, p2]| {
+                let d1 = [p1[0] - p0[0], p1[1] - p0[1]];
+                let d2 = [p2[0] - p0[0], p2[1] - p0[1]];
+                0.5 * cross(*p0, *p2) + cross(d1, d2) / 3.0
+            })
+            .sum()
+    }
+
+    fn inside(curves: &[Curve], axis: usize, lo: f32, hi: f32) -> bool {
+        let eps = 1e-6;
+        curves
+            .iter()
+            .flatten()
+            .all(|p| p[axis] >= lo - eps && p[axis] <= hi + eps)
+    }
+
+    fn square() -> Vec<Curve> {
+        let corners = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        (0..4)
+            .map(|i| {
+                let (a, b): ([f32; 2], [f32; 2]) = (corners[i], corners[(i + 1) % 4]);
+                [a, [0.5 * (a[0] + b[0]), 0.5 * (a[1] + b[1])], b]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clip_lines() {
+        let clipped = clip(&square(), 1, 0.25, 0.5);
+        assert!(inside(&clipped, 1, 0.25, 0.5));
+        assert!((area(&clipped) - 0.25).abs() < 1e-6);
+
+        let clipped = clip(&clipped, 0, -1.0, 0.5);
+        assert!((area(&clipped) - 0.125).abs() < 1e-6);
+
+        // Everything outside the slab is flattened onto its edge.
+        assert_eq!(area(&clip(&square(), 1, 2.0, 3.0)), 0.0);
+    }
+
+    #[test]
+    fn clip_curves() {
+        // The area under the parabola y = x (2 - x).
+        let hump = [
+            [[0.0, 0.0], [1.0, 0.0], [2.0, 0.0]],
+            [[2.0, 0.0], [1.0, 2.0], [0.0, 0.0]],
+        ];
+        assert!((area(&hump) - 4.0 / 3.0).abs() < 1e-6);
+
+        let (y0, y1, x0, x1) = (0.25, 0.5, 0.2, 1.5);
+        let clipped = clip(&clip(&hump, 1, y0, y1), 0, x0, x1);
+        assert!(inside(&clipped, 1, y0, y1));
+        assert!(inside(&clipped, 0, x0, x1));
+
+        let n = 100_000;
+        let dx = (x1 - x0) / n as f32;
+        let expected: f32 = (0..n)
+            .map(|i| {
+                let x = x0 + (i as f32 + 0.5) * dx;
+                (x * (2.0 - x)).clamp(y0, y1) - y0
+            })
+            .sum::<f32>()
+            * dx;
+        assert!((area(&clipped) - expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn superscripts_share_the_underline() {
+        let atlas = Atlas::new(&LM_ROMAN);
+        let font = atlas.metrics();
+        let layers = RichText::new()
+            .span(Span::new("x", &atlas))
+            .span(Span::new("2", &atlas).size(0.7).shift(0.4))
+            .span(Span::new("+y", &atlas))
+            .layout();
+        let rects = rects(&layers[0].outline, &font, Decoration::Underline, false);
+        assert_eq!(rects.len(), 1);
+        let y = font.underline.position - 0.5 * font.underline.thickness;
+        assert!((rects[0].y0 - y).abs() < 1e-6);
+    }
+
+    #[test]
+    fn every_line_is_decorated() {
+        let atlas = Atlas::new(&LM_ROMAN);
+        let font = atlas.metrics();
+        let layout = Paragraph::new(3.0).layout("one two three four", &atlas);
+        let rects = rects(&layout.outline, &font, Decoration::Strikethrough, false);
+        assert_eq!(rects.len(), layout.lines.len());
+        assert!(rects.len() > 1);
+        for (rect, line) in rects.iter().zip(&layout.lines) {
+            let y = line.baseline + font.strikeout.position - 0.5 * font.strikeout.thickness;
+            assert!((rect.y0 - y).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "vertical text")]
+    fn vertical_text_is_rejected() {
+        let atlas = Atlas::new(&LM_ROMAN);
+        let mut outline = atlas.outline_vertical("up", &ShapingOptions::new());
+        decorate(&mut outline, &atlas.metrics(), Decoration::Underline, false);
+    }
+}
@@ -1,14 +1,23 @@
 //! Font loading.
-//! At the time of writing this module just contains lazy-static
-//! definitions of LaTeX font faces baked into the binary. This
-//! is not elegant since it bloats the binary, but the fonts are
+//! The LaTeX font faces are baked into the binary as lazy-static definitions.
+//! This is not elegant since it bloats the binary, but the fonts are
 //! actually quite small (couple hundred K) and Rust-binaries are
 //! already huge (> 50M at time of writing), so I'd say its a good
 //! tradeoff for not having to deal with fonts missing and most
 //! importantly, trying to figure out where the fuck the fonts are
 //! on Windows.
+//!
+//! Other faces can be loaded from files or byte buffers at runtime, and
+//! registered under a family and style name in a `FontRegistry`.
+use crate::atlas::{Atlas, Fallback};
+use crate::variation::Variation;
 use lazy_static::lazy_static;
 use rustybuzz::Face;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 lazy_static! {
     pub static ref LM_MATH: Face<'static> = {
@@ -16,4 +25,246 @@ lazy_static! {
         // Unwrap OK. Can't really fail with the bytes baked in the binary.
         rustybuzz::Face::from_slice(bytes, 0).unwrap()
     };
+    pub static ref LM_ROMAN: Face<'static> = {
+        let bytes = include_bytes!("../res/lm/lmroman10-regular.otf");
+        rustybuzz::Face::from_slice(bytes, 0).unwrap()
+    };
+    pub static ref LM_ROMAN_ITALIC: Face<'static> = {
+        let bytes = include_bytes!("../res/lm/lmroman10-italic.otf");
+        rustybuzz::Face::from_slice(bytes, 0).unwrap()
+    };
+    pub static ref LM_ROMAN_BOLD: Face<'static> = {
+        let bytes = include_bytes!("../res/lm/lmroman10-bold.otf");
+        rustybuzz::Face::from_slice(bytes, 0).unwrap()
+    };
+    pub static ref LM_ROMAN_BOLD_ITALIC: Face<'static> = {
+        let bytes = include_bytes!("../res/lm/lmroman10-bolditalic.otf");
+        rustybuzz::Face::from_slice(bytes, 0).unwrap()
+    };
+    pub static ref LM_ROMAN_CAPS: Face<'static> = {
+        let bytes = include_bytes!("../res/lm/lmromancaps10-regular.otf");
+        rustybuzz::Face::from_slice(bytes, 0).unwrap()
+    };
+    pub static ref LM_ROMAN_CAPS_OBLIQUE: Face<'static> = {
+        let bytes = include_bytes!("../res/lm/lmromancaps10-oblique.otf");
+        rustybuzz::Face::from_slice(bytes, 0).unwrap()
+    };
+}
+
+/// Family name of the bundled Latin Modern text fonts.
+pub const LATIN_MODERN_ROMAN: &str = "Latin Modern Roman";
+/// Family name of the bundled Latin Modern math font.
+pub const LATIN_MODERN_MATH: &str = "Latin Modern Math";
+
+/// The style of a face within its family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Style {
+    Regular,
+    Italic,
+    Bold,
+    BoldItalic,
+    Caps,
+    CapsOblique,
+}
+
+#[derive(Debug)]
+pub enum FontError {
+    /// The font file could not be read.
+    Io(std::io::Error),
+    /// The data is not a font, or there is no face at the given index.
+    Parse,
+}
+
+impl From<std::io::Error> for FontError {
+    fn from(err: std::io::Error) -> Self {
+        FontError::Io(err)
+    }
+}
+
+/// Load a face from a font file.
+pub fn load_file<P: AsRef<Path>>(path: P, index: u32) -> Result<&'static Face<'static>, FontError> {
+    let bytes = std::fs::read(path)?;
+    load_bytes(bytes, index)
+}
+
+/// A font that was loaded at runtime, and the faces of it that were asked for.
+struct Loaded {
+    hash: u64,
+    data: &'static [u8],
+    faces: Vec<(u32, &'static Face<'static>)>,
+}
+
+/// Every font loaded so far, so loading one again doesn't leak it again.
+static LOADED: Mutex<Vec<Loaded>> = Mutex::new(Vec::new());
+
+/// Load a face from a buffer containing a font file.
+///
+/// The face borrows from the buffer, and the atlas borrows from the face, so to keep things
+/// simple, both are leaked and live until the program exits. Loading a font with the same
+/// data again (from the same file, say) returns the face that was loaded first, so reloading
+/// fonts only takes memory for fonts that are new.
+pub fn load_bytes(bytes: Vec<u8>, index: u32) -> Result<&'static Face<'static>, FontError> {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    let hash = hasher.finish();
+
+    let mut loaded = LOADED.lock().unwrap();
+    let font = match loaded.iter().position(|l| l.hash == hash && *l.data == bytes[..]) {
+        Some(i) => &mut loaded[i],
+        None => {
+            // Don't keep data that isn't a font.
+            Face::from_slice(&bytes, index).ok_or(FontError::Parse)?;
+            loaded.push(Loaded {
+                hash,
+                data: Box::leak(bytes.into_boxed_slice()),
+                faces: Vec::new(),
+            });
+            loaded.last_mut().unwrap()
+        }
+    };
+
+    if let Some(&(_, face)) = font.faces.iter().find(|(i, _)| *i == index) {
+        return Ok(face);
+    }
+    let face = Face::from_slice(font.data, index).ok_or(FontError::Parse)?;
+    let face: &'static Face<'static> = Box::leak(Box::new(face));
+    font.faces.push((index, face));
+    Ok(face)
+}
+
+/// A collection of faces, looked up by family and style.
+/// The atlases of the faces are built on demand, and shared.
+pub struct FontRegistry {
+    faces: HashMap<(String, Style), &'static Face<'static>>,
+    atlases: HashMap<(String, Style), Arc<Atlas<'static>>>,
+    // Where to keep precomputed outlines, if anywhere. (See `Atlas::cached`)
+    cache_dir: Option<PathBuf>,
+}
+
+impl FontRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        FontRegistry {
+            faces: HashMap::new(),
+            atlases: HashMap::new(),
+            cache_dir: None,
+        }
+    }
+
+    /// Load the atlases from (and save them to) precomputed outline files in `dir`, instead
+    /// of outlining the glyphs from the font. Useful for batch jobs that render lots of figures.
+    pub fn with_cache<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.cache_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// A registry containing the Latin Modern faces baked into the binary.
+    pub fn latin_modern() -> Self {
+        let mut registry = FontRegistry::new();
+        registry.register(LATIN_MODERN_MATH, Style::Regular, &LM_MATH);
+        registry.register(LATIN_MODERN_ROMAN, Style::Regular, &LM_ROMAN);
+        registry.register(LATIN_MODERN_ROMAN, Style::Italic, &LM_ROMAN_ITALIC);
+        registry.register(LATIN_MODERN_ROMAN, Style::Bold, &LM_ROMAN_BOLD);
+        registry.register(LATIN_MODERN_ROMAN, Style::BoldItalic, &LM_ROMAN_BOLD_ITALIC);
+        registry.register(LATIN_MODERN_ROMAN, Style::Caps, &LM_ROMAN_CAPS);
+        registry.register(LATIN_MODERN_ROMAN, Style::CapsOblique, &LM_ROMAN_CAPS_OBLIQUE);
+        registry
+    }
+
+    /// Register a face under a family and style. This replaces any face (and atlas)
+    /// that was previously registered under the same name.
+    pub fn register(&mut self, family: &str, style: Style, face: &'static Face<'static>) {
+        let key = (family.to_string(), style);
+        self.atlases.remove(&key);
+        self.faces.insert(key, face);
+    }
+
+    /// Load a face from a file, and register it.
+    pub fn load<P: AsRef<Path>>(
+        &mut self,
+        family: &str,
+        style: Style,
+        path: P,
+    ) -> Result<&'static Face<'static>, FontError> {
+        let face = load_file(path, 0)?;
+        self.register(family, style, face);
+        Ok(face)
+    }
+
+    pub fn face(&self, family: &str, style: Style) -> Option<&'static Face<'static>> {
+        self.faces.get(&(family.to_string(), style)).copied()
+    }
+
+    /// The atlas of a registered face. The atlas is created on the first call for each face,
+    /// and the glyphs are outlined as they are used.
+    pub fn atlas(&mut self, family: &str, style: Style) -> Option<Arc<Atlas<'static>>> {
+        let key = (family.to_string(), style);
+        let face = *self.faces.get(&key)?;
+        let cache_dir = &self.cache_dir;
+        let atlas = self.atlases.entry(key).or_insert_with(|| match cache_dir {
+            Some(dir) => Arc::new(Atlas::cached(face, dir)),
+            None => Arc::new(Atlas::new(face)),
+        });
+        Some(atlas.clone())
+    }
+
+    /// Move the atlas of a registered face to another point on the axes of a variable font.
+    /// (See `Atlas::vary`) Atlases handed out before keep their variations, so get the atlas
+    /// again and lay out the text again to see the change.
+    pub fn set_variations(
+        &mut self,
+        family: &str,
+        style: Style,
+        variations: &[Variation],
+    ) -> Option<Arc<Atlas<'static>>> {
+        let atlas = Arc::new(self.atlas(family, style)?.vary(variations));
+        self.atlases.insert((family.to_string(), style), atlas.clone());
+        Some(atlas)
+    }
+
+    /// A fallback chain of registered faces, tried in the given order. Names that are not
+    /// registered are skipped. Returns `None` if none of them are.
+    pub fn fallback(&mut self, names: &[(&str, Style)]) -> Option<Fallback<'static>> {
+        let atlases: Vec<_> = names
+            .iter()
+            .filter_map(|&(family, style)| self.atlas(family, style))
+            .collect();
+
+        if atlases.is_empty() {
+            return None;
+        }
+
+        Some(Fallback::new(atlases))
+    }
+
+    /// All the registered (family, style) pairs.
+    pub fn names(&self) -> impl Iterator<Item = (&str, Style)> {
+        self.faces.keys().map(|(family, style)| (family.as_str(), *style))
+    }
+}
+
+impl Default for FontRegistry {
+    fn default() -> Self {
+        FontRegistry::latin_modern()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loading_a_font_again_gives_the_same_face() {
+        let bytes = include_bytes!("../res/lm/lmroman10-italic.otf");
+        let face = load_bytes(bytes.to_vec(), 0).unwrap();
+        assert!(std::ptr::eq(face, load_bytes(bytes.to_vec(), 0).unwrap()));
+
+        let other = load_bytes(include_bytes!("../res/lm/lmroman10-bold.otf").to_vec(), 0);
+        assert!(!std::ptr::eq(face, other.unwrap()));
+
+        // Data that isn't a font is not kept.
+        assert!(matches!(load_bytes(vec![0; 64], 0), Err(FontError::Parse)));
+        let loaded = LOADED.lock().unwrap();
+        assert!(loaded.iter().all(|l| l.data.len() > 64));
+    }
 }
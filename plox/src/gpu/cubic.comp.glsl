@@ -0,0 +1,90 @@
+#version 430
+
+// Tesselates a single cubic Bézier curve into the vertex- and uv-buffers
+// of a line. One invocation per sample point on the curve, each of which
+// writes the pair of vertices on either side of the curve.
+//
+// Like on the CPU, uv.x is the length along the line (of the chords between
+// the sample points) divided by the length of the whole line. Each invocation
+// just adds up all the chords, there are at most a thousand.
+
+layout(local_size_x = 64) in;
+
+layout(std430, binding = 0) readonly buffer Ctrl {
+    vec2 ctrl[4];
+};
+
+layout(std430, binding = 1) writeonly buffer Positions {
+    vec2 positions[];
+};
+
+layout(std430, binding = 2) writeonly buffer Uvs {
+    vec2 uvs[];
+};
+
+uniform float width;
+uniform int segments;
+
+vec2 r(float t) {
+    float s = 1 - t;
+    return s*s*s*ctrl[0] + 3*s*s*t*ctrl[1] + 3*s*t*t*ctrl[2] + t*t*t*ctrl[3];
+}
+
+vec2 dr(float t) {
+    float s = 1 - t;
+    return 3*s*s*(ctrl[1] - ctrl[0]) + 6*s*t*(ctrl[2] - ctrl[1]) + 3*t*t*(ctrl[3] - ctrl[2]);
+}
+
+// The length of the chords up to sample point i, and of all of them.
+vec2 arc_length(int i) {
+    float l = 0;
+    float total = 0;
+    vec2 prev = r(0);
+    for (int j = 1; j <= segments; j++) {
+        vec2 q = r(float(j) / float(segments));
+        total += length(q - prev);
+        prev = q;
+        if (j == i) {
+            l = total;
+        }
+    }
+    return vec2(l, total);
+}
+
+void main() {
+    int i = int(gl_GlobalInvocationID.x);
+    if (i > segments) {
+        return;
+    }
+
+    float t = float(i) / float(segments);
+    vec2 p = r(t);
+    vec2 d = dr(t);
+
+    // Coinciding control points make the derivative vanish at the ends.
+    // Fall back to a finite difference to get a direction.
+    if (length(d) < 1e-6) {
+        float h = 1.0 / float(segments);
+        d = r(min(t + h, 1)) - r(max(t - h, 0));
+    }
+
+    // vw-basis for the local space of the curve (see report). For a smooth
+    // curve, the normal is exactly the direction of the miter.
+    vec2 v = normalize(d);
+    vec2 w = vec2(-v.y, v.x);
+
+    // Square caps at the ends.
+    if (i == 0) {
+        p -= width * v;
+    }
+    if (i == segments) {
+        p += width * v;
+    }
+
+    positions[2*i]     = p - width * w;
+    positions[2*i + 1] = p + width * w;
+    vec2 l = arc_length(i);
+    float u = l.x / l.y;
+    uvs[2*i]     = vec2(u, 0);
+    uvs[2*i + 1] = vec2(u, 1);
+}
@@ -0,0 +1,24 @@
+#version 440
+
+// Like fill.vert.glsl, but the glyphs are instances of outlines in a storage buffer, shared
+// by all text. (See `GlyphBuffer` in text.rs) Each draw is one glyph, so the vertex id
+// indexes straight into the buffer.
+layout(std430, binding = 0) readonly buffer Points {
+    vec2 points[];
+};
+
+// Position of the glyph, and whether it is turned. (See `shaping::Glyph::rotated`)
+in vec3 instance;
+uniform mat4 mvp;
+
+void main() {
+    vec2 p = points[gl_VertexID];
+    if (instance.z != 0.0) {
+        p = vec2(p.y, -p.x);
+    }
+
+    // This sends the middle control point to the origin of the glyph. Any point works for
+    // the flipping, and this one keeps the triangles small.
+    float mask = abs(gl_VertexID % 3 - 1);
+    gl_Position = mvp * vec4(instance.xy + mask * p, 0.0, 1.0);
+}
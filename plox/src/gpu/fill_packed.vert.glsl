@@ -0,0 +1,35 @@
+#version 440
+
+// Like fill.vert.glsl, but the points come packed from a storage buffer. (See packed.rs)
+layout(std430, binding = 0) readonly buffer Points {
+    uint words[];
+};
+
+uniform mat4 mvp;
+uniform int packing;
+uniform bool shared_points;
+// Units per em of fixed point coordinates.
+uniform float em;
+// The points are relative to the origin of their group, which is one instance per draw.
+in vec2 origin;
+
+vec2 point(int i) {
+    switch (packing) {
+    case 0:
+        return vec2(uintBitsToFloat(words[2 * i]), uintBitsToFloat(words[2 * i + 1]));
+    case 1:
+        return unpackHalf2x16(words[i]);
+    default:
+        int w = int(words[i]);
+        return vec2(bitfieldExtract(w, 0, 16), bitfieldExtract(w, 16, 16)) / em;
+    }
+}
+
+void main() {
+    int role = gl_VertexID % 3;
+    int i = shared_points ? 2 * (gl_VertexID / 3) + role : gl_VertexID;
+
+    // This sends the middle control point to the origin of the group.
+    float mask = abs(role - 1);
+    gl_Position = mvp * vec4(origin + mask * point(i), 0.0, 1.0);
+}
@@ -1,6 +1,7 @@
 #version 430
 
 in  vec2 uv;
+in  vec4 line_color;
 out vec4 color;
 
 uniform float width;
@@ -14,5 +15,5 @@ void main() {
     float dL = fwidth(L);
     float beta = 1 - clamp((L + dL) / dL, 0, 1);
 
-    color = vec4(0, 0, 0, alpha*beta);
+    color = vec4(line_color.rgb, line_color.a*alpha*beta);
 }
@@ -1,12 +1,19 @@
 #version 430
 
-in  vec2 position;
-in  vec2 uv_in;
-out vec2 uv;
+layout(location = 0) in vec2  position;
+layout(location = 1) in vec2  uv_in;
+layout(location = 2) in vec4  color_in;
+layout(location = 3) in float value_in;
+
+out vec2  uv;
+out vec4  line_color;
+out float value;
 
 uniform mat4 mvp;
 
 void main() {
     gl_Position = mvp * vec4(position, 0.0, 1.0);
     uv = uv_in;
+    line_color = color_in;
+    value = value_in;
 }
@@ -0,0 +1,26 @@
+#version 430
+
+in  vec2  uv;
+in  float value;
+out vec4  color;
+
+uniform float width;
+uniform sampler2D colormap;
+
+void main() {
+    float W = abs(width * (2*uv.y - 1)) - width;
+    float dW = fwidth(W);
+    float alpha = 1 - clamp((W + dW) / dW, 0, 1);
+
+    float L = abs(uv.x - 0.5) - 0.5;
+    float dL = fwidth(L);
+    float beta = 1 - clamp((L + dL) / dL, 0, 1);
+
+    // Sample between the centers of the first and last texel, so the
+    // ends of the range map exactly to the ends of the colormap.
+    float n = textureSize(colormap, 0).x;
+    float s = (0.5 + clamp(value, 0, 1) * (n - 1)) / n;
+    vec4 C = texture(colormap, vec2(s, 0.5));
+
+    color = vec4(C.rgb, C.a*alpha*beta);
+}
@@ -19,6 +19,8 @@ pub mod circle; // circle, circle arc rendering
 pub mod shader;
 pub mod text; // text rendering
 pub mod typeset;
+#[cfg(test)]
+pub(crate) mod testing;
 
 use gl::types::*;
 use std::ptr;
@@ -54,6 +56,11 @@ pub struct Ssbo {
     buffer: GLuint,
 }
 
+/// Buffer of draw commands, for indirect draws.
+pub struct Dibo {
+    buffer: GLuint,
+}
+
 pub struct Texture {
     pub tex: GLuint,
 }
@@ -85,6 +92,14 @@ pub unsafe fn gl_viewport() -> (GLint, GLint, GLint, GLint) {
     (vp[0], vp[1], vp[2], vp[3])
 }
 
+/// The (major, minor) version of the current OpenGL context.
+pub unsafe fn gl_version() -> (GLint, GLint) {
+    let (mut major, mut minor) = (0, 0);
+    gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+    gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+    (major, minor)
+}
+
 //
 // Implementations
 //
@@ -122,6 +137,13 @@ impl<const N: u32> Vao<N> {
         gl::VertexAttribIPointer(index, size, ty, stride, pointer);
     }
 
+    /// Advance the attribute once per `divisor` instances instead of once per vertex.
+    #[inline(always)]
+    pub unsafe fn attrib_divisor(&self, index: GLuint, divisor: GLuint) {
+        gl::BindVertexArray(self.array);
+        gl::VertexAttribDivisor(index, divisor);
+    }
+
     #[inline(always)]
     pub unsafe fn bind(&self) {
         gl::BindVertexArray(self.array);
@@ -151,6 +173,38 @@ impl Vbo {
             gl::STATIC_DRAW,
         );
     }
+
+    /// Overwrite part of the buffer, starting at element `offset`. The buffer has to be big
+    /// enough already.
+    #[inline(always)]
+    pub unsafe fn sub_data<T>(&self, offset: usize, vertices: &[T]) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer);
+        gl::BufferSubData(
+            gl::ARRAY_BUFFER,
+            (offset * std::mem::size_of::<T>()) as GLintptr,
+            gl_buf_size(vertices),
+            gl_ptr(vertices),
+        );
+    }
+
+    /// Allocate room for `n` elements of type T without uploading anything.
+    /// Used for buffers that are written by a compute shader.
+    #[inline(always)]
+    pub unsafe fn reserve<T>(&self, n: usize) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            (n * std::mem::size_of::<T>()) as GLsizeiptr,
+            ptr::null(),
+            gl::DYNAMIC_COPY,
+        );
+    }
+
+    /// Bind the vertex buffer as a shader storage buffer, so a compute shader can write to it.
+    #[inline(always)]
+    pub unsafe fn bind_base(&self, index: u32) {
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, index, self.buffer);
+    }
 }
 
 impl Ibo {
@@ -202,12 +256,62 @@ impl Ssbo {
         );
     }
 
+    /// Overwrite part of the buffer, starting at element `offset`. The buffer has to be big
+    /// enough already.
+    #[inline(always)]
+    pub unsafe fn sub_data<T>(&self, offset: usize, ssbo: &[T]) {
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.buffer);
+        gl::BufferSubData(
+            gl::SHADER_STORAGE_BUFFER,
+            (offset * std::mem::size_of::<T>()) as GLintptr,
+            gl_buf_size(ssbo),
+            gl_ptr(ssbo),
+        );
+    }
+
+    /// Allocate room for `n` elements of type T without uploading anything.
+    #[inline(always)]
+    pub unsafe fn reserve<T>(&self, n: usize) {
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.buffer);
+        gl::BufferData(
+            gl::SHADER_STORAGE_BUFFER,
+            (n * std::mem::size_of::<T>()) as GLsizeiptr,
+            ptr::null(),
+            gl::STATIC_DRAW,
+        );
+    }
+
     #[inline(always)]
     pub unsafe fn bind_base(&self, index: u32) {
         gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, index, self.buffer);
     }
 }
 
+impl Dibo {
+    #[inline(always)]
+    pub unsafe fn gen() -> Self {
+        let mut buffer = 0;
+        gl::GenBuffers(1, &mut buffer);
+        Dibo { buffer }
+    }
+
+    #[inline(always)]
+    pub unsafe fn bind(&self) {
+        gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.buffer);
+    }
+
+    #[inline(always)]
+    pub unsafe fn data<T>(&self, commands: &[T]) {
+        gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.buffer);
+        gl::BufferData(
+            gl::DRAW_INDIRECT_BUFFER,
+            gl_buf_size(commands),
+            gl_ptr(commands),
+            gl::STATIC_DRAW,
+        );
+    }
+}
+
 impl Texture {
     #[inline(always)]
     pub unsafe fn bind(&self) {
@@ -241,6 +345,36 @@ impl Texture {
         gl::BindTexture(gl::TEXTURE_2D, 0);
         Texture { tex }
     }
+
+    /// Create a 1 pixel tall RGBA texture from a list of colours. Used as a colormap, so
+    /// sampling it interpolates linearly between the colours.
+    #[inline(always)]
+    pub unsafe fn colormap(colors: &[glm::Vec4]) -> Texture {
+        let mut tex = 0;
+        gl::GenTextures(1, &mut tex);
+        gl::BindTexture(gl::TEXTURE_2D, tex);
+
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,                  /* Level of detail */
+            gl::RGBA32F as i32, /* Internal format */
+            colors.len() as i32,
+            1,
+            0,         /* Border. (Must be zero per the docs) */
+            gl::RGBA,  /* Format for pixel data. */
+            gl::FLOAT, /* Type of pixel data. */
+            gl_ptr(colors),
+        );
+
+        // Interpolate between the colours, and don't wrap around at the ends.
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        Texture { tex }
+    }
 }
 
 // Really, this could just be a glm::mat4
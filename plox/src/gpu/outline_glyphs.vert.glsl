@@ -0,0 +1,31 @@
+#version 440
+
+// Like outline.vert.glsl, for instanced glyphs. (See fill_glyphs.vert.glsl)
+layout(std430, binding = 0) readonly buffer Points {
+    vec2 points[];
+};
+
+in vec3 instance;
+out vec2 uv;
+
+uniform mat4 mvp;
+
+void main() {
+    vec2 p = points[gl_VertexID];
+    if (instance.z != 0.0) {
+        p = vec2(p.y, -p.x);
+    }
+    gl_Position = mvp * vec4(instance.xy + p, 0.0, 1.0);
+
+    switch (gl_VertexID % 3) {
+    case 0:
+        uv = vec2(0.0, 0.0);
+        break;
+    case 1:
+        uv = vec2(0.5, 0.0);
+        break;
+    case 2:
+        uv = vec2(1.0, 1.0);
+        break;
+    }
+}
@@ -0,0 +1,47 @@
+#version 440
+
+// Like outline.vert.glsl, but the points come packed from a storage buffer. (See packed.rs)
+layout(std430, binding = 0) readonly buffer Points {
+    uint words[];
+};
+
+out vec2 uv;
+
+uniform mat4 mvp;
+uniform int packing;
+uniform bool shared_points;
+// Units per em of fixed point coordinates.
+uniform float em;
+// The points are relative to the origin of their group, which is one instance per draw.
+in vec2 origin;
+
+vec2 point(int i) {
+    switch (packing) {
+    case 0:
+        return vec2(uintBitsToFloat(words[2 * i]), uintBitsToFloat(words[2 * i + 1]));
+    case 1:
+        return unpackHalf2x16(words[i]);
+    default:
+        int w = int(words[i]);
+        return vec2(bitfieldExtract(w, 0, 16), bitfieldExtract(w, 16, 16)) / em;
+    }
+}
+
+void main() {
+    int role = gl_VertexID % 3;
+    int i = shared_points ? 2 * (gl_VertexID / 3) + role : gl_VertexID;
+
+    gl_Position = mvp * vec4(origin + point(i), 0.0, 1.0);
+
+    switch (role) {
+    case 0:
+        uv = vec2(0.0, 0.0);
+        break;
+    case 1:
+        uv = vec2(0.5, 0.0);
+        break;
+    case 2:
+        uv = vec2(1.0, 1.0);
+        break;
+    }
+}
@@ -1,5 +1,6 @@
 //! Shader abstraction.
-use gl::{types::*, FRAGMENT_SHADER, VERTEX_SHADER};
+use crate::gpu::Texture;
+use gl::{types::*, COMPUTE_SHADER, FRAGMENT_SHADER, VERTEX_SHADER};
 use std::ffi::CString;
 use std::ptr;
 use std::sync::Arc;
@@ -38,6 +39,7 @@ uniform!(UniformVec2);
 uniform!(UniformVec2i);
 uniform!(UniformVec4);
 uniform!(UniformFloat);
+uniform!(UniformInt);
 
 // Shaders programs:
 // I just include them in the binary, so it is guaranteed that the
@@ -46,10 +48,15 @@ const TXT_FILL_FRAG: &str = include_str!("fill.frag.glsl");
 const TXT_FILL_VERT: &str = include_str!("fill.vert.glsl");
 const TXT_OUTLINE_FRAG: &str = include_str!("outline.frag.glsl");
 const TXT_OUTLINE_VERT: &str = include_str!("outline.vert.glsl");
+const TXT_FILL_PACKED_VERT: &str = include_str!("fill_packed.vert.glsl");
+const TXT_OUTLINE_PACKED_VERT: &str = include_str!("outline_packed.vert.glsl");
+const TXT_FILL_GLYPHS_VERT: &str = include_str!("fill_glyphs.vert.glsl");
+const TXT_OUTLINE_GLYPHS_VERT: &str = include_str!("outline_glyphs.vert.glsl");
 
 const TXT_BLIT_VERT: &str = include_str!("textelement.vert.glsl");
 const TXT_BLIT_FRAG: &str = include_str!("textelement_simple.frag.glsl");
 const TXT_BLIT_FRAG_FANCY: &str = include_str!("textelement_fancy.frag.glsl");
+const TXT_BLIT_FRAG_COLOR: &str = include_str!("textelement_color.frag.glsl");
 
 const CIRCLE_VERT: &str = include_str!("circle.vert.glsl");
 const CIRCLE_FRAG: &str = include_str!("circle.frag.glsl");
@@ -58,6 +65,9 @@ const CIRCLE_FRAG_FANCY: &str = include_str!("circle_fancy.frag.glsl");
 const LINE_VERT: &str = include_str!("line.vert.glsl");
 const LINE_FRAG: &str = include_str!("line.frag.glsl");
 const LINE_FRAG_FANCY: &str = include_str!("line_fancy.frag.glsl");
+const LINE_FRAG_COLORMAP: &str = include_str!("line_colormap.frag.glsl");
+
+const CUBIC_COMP: &str = include_str!("cubic.comp.glsl");
 
 impl Shader {
     pub unsafe fn fill() -> Shader {
@@ -84,6 +94,58 @@ impl Shader {
         Shader { shader: program, on_bind: None }
     }
 
+    /// Like `fill`, for packed outlines. (See `packed::PackedOutline`)
+    pub unsafe fn fill_packed() -> Shader {
+        let vert = Shader::compile(VERTEX_SHADER, TXT_FILL_PACKED_VERT);
+        let frag = Shader::compile(FRAGMENT_SHADER, TXT_FILL_FRAG);
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, vert);
+        gl::AttachShader(program, frag);
+        Shader::link(program);
+        gl::DeleteShader(vert);
+        gl::DeleteShader(frag);
+        Shader { shader: program, on_bind: None }
+    }
+
+    /// Like `outline`, for packed outlines.
+    pub unsafe fn outline_packed() -> Shader {
+        let vert = Shader::compile(VERTEX_SHADER, TXT_OUTLINE_PACKED_VERT);
+        let frag = Shader::compile(FRAGMENT_SHADER, TXT_OUTLINE_FRAG);
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, vert);
+        gl::AttachShader(program, frag);
+        Shader::link(program);
+        gl::DeleteShader(vert);
+        gl::DeleteShader(frag);
+        Shader { shader: program, on_bind: None }
+    }
+
+    /// Like `fill`, for glyphs instanced from a `GlyphBuffer`.
+    pub unsafe fn fill_glyphs() -> Shader {
+        let vert = Shader::compile(VERTEX_SHADER, TXT_FILL_GLYPHS_VERT);
+        let frag = Shader::compile(FRAGMENT_SHADER, TXT_FILL_FRAG);
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, vert);
+        gl::AttachShader(program, frag);
+        Shader::link(program);
+        gl::DeleteShader(vert);
+        gl::DeleteShader(frag);
+        Shader { shader: program, on_bind: None }
+    }
+
+    /// Like `outline`, for glyphs instanced from a `GlyphBuffer`.
+    pub unsafe fn outline_glyphs() -> Shader {
+        let vert = Shader::compile(VERTEX_SHADER, TXT_OUTLINE_GLYPHS_VERT);
+        let frag = Shader::compile(FRAGMENT_SHADER, TXT_OUTLINE_FRAG);
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, vert);
+        gl::AttachShader(program, frag);
+        Shader::link(program);
+        gl::DeleteShader(vert);
+        gl::DeleteShader(frag);
+        Shader { shader: program, on_bind: None }
+    }
+
     pub unsafe fn simple_blit() -> Shader {
         let vert = Shader::compile(VERTEX_SHADER, TXT_BLIT_VERT);
         let frag = Shader::compile(FRAGMENT_SHADER, TXT_BLIT_FRAG);
@@ -108,6 +170,20 @@ impl Shader {
         Shader { shader: program, on_bind: None }
     }
 
+    /// Text shader with a `text_color` uniform. Used for the layers of colour glyphs, and
+    /// anything else that needs text of a given colour.
+    pub unsafe fn color_blit() -> Shader {
+        let vert = Shader::compile(VERTEX_SHADER, TXT_BLIT_VERT);
+        let frag = Shader::compile(FRAGMENT_SHADER, TXT_BLIT_FRAG_COLOR);
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, vert);
+        gl::AttachShader(program, frag);
+        Shader::link(program);
+        gl::DeleteShader(vert);
+        gl::DeleteShader(frag);
+        Shader { shader: program, on_bind: None }
+    }
+
     pub unsafe fn circle() -> Shader {
         let vert = Shader::compile(VERTEX_SHADER, CIRCLE_VERT);
         let frag = Shader::compile(FRAGMENT_SHADER, CIRCLE_FRAG);
@@ -156,6 +232,37 @@ impl Shader {
         Shader { shader: program, on_bind: None }
     }
 
+    /// Line shader that colours the line by looking up its per-vertex values in a colormap.
+    pub unsafe fn colormap_line(colormap: &Texture) -> Shader {
+        let vert = Shader::compile(VERTEX_SHADER, LINE_VERT);
+        let frag = Shader::compile(FRAGMENT_SHADER, LINE_FRAG_COLORMAP);
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, vert);
+        gl::AttachShader(program, frag);
+        Shader::link(program);
+        gl::DeleteShader(vert);
+        gl::DeleteShader(frag);
+        let mut shader = Shader { shader: program, on_bind: None };
+
+        // Make sure the colormap is bound whenever the shader is.
+        let tex = colormap.tex;
+        shader.on_bind(move || unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, tex);
+        });
+
+        shader
+    }
+
+    /// Compute shader that tesselates cubic Bézier curves. Requires OpenGL 4.3.
+    pub unsafe fn cubic_tesselation() -> Shader {
+        let comp = Shader::compile(COMPUTE_SHADER, CUBIC_COMP);
+        let program = gl::CreateProgram();
+        gl::AttachShader(program, comp);
+        Shader::link(program);
+        gl::DeleteShader(comp);
+        Shader { shader: program, on_bind: None }
+    }
+
     pub fn on_bind(&mut self, callback: impl 'static + Fn()) {
         self.on_bind = Some(Arc::new(callback));
     }
@@ -176,6 +283,11 @@ impl Shader {
         panic!("invalid uniform {}", name)
     }
 
+    /// Like `uniform`, for uniforms that not every shader of a kind has.
+    pub unsafe fn try_uniform<U: Uniform>(&self, name: &str) -> Option<U> {
+        self.uniform_location(name).map(U::wrap)
+    }
+
     unsafe fn uniform_location(&self, name: &str) -> Option<GLint> {
         self.bind();
         let name = CString::new(name).ok()?;
@@ -286,3 +398,10 @@ impl UniformFloat {
         gl::Uniform1f(self.0, x);
     }
 }
+
+impl UniformInt {
+    #[inline(always)]
+    pub unsafe fn data(&self, x: i32) {
+        gl::Uniform1i(self.0, x);
+    }
+}
@@ -0,0 +1,105 @@
+//! A headless OpenGL context for tests.
+//!
+//! The context is made with EGL on Mesa's surfaceless platform, which needs neither a window
+//! system nor a GPU (Mesa falls back to llvmpipe). Tests that need it are ignored by default,
+//! run them with `cargo test -p plox -- --ignored`.
+use super::{gl_buf_size, gl_mut_ptr, Ssbo, Vbo};
+use khronos_egl as egl;
+use std::ptr;
+
+/// `EGL_PLATFORM_SURFACELESS_MESA`
+const PLATFORM_SURFACELESS: egl::Enum = 0x31DD;
+
+/// An OpenGL 4.3 core context, current on the thread that made it. Each test makes its own.
+pub struct Context {
+    egl: egl::DynamicInstance<egl::EGL1_5>,
+    display: egl::Display,
+    context: egl::Context,
+}
+
+pub fn context() -> Context {
+    let egl = unsafe { egl::DynamicInstance::<egl::EGL1_5>::load_required() }
+        .expect("could not load libEGL");
+
+    let display = unsafe {
+        egl.get_platform_display(
+            PLATFORM_SURFACELESS,
+            egl::DEFAULT_DISPLAY,
+            &[egl::ATTRIB_NONE],
+        )
+    }
+    .expect("no surfaceless EGL display");
+    egl.initialize(display).expect("could not initialize EGL");
+    egl.bind_api(egl::OPENGL_API).expect("no desktop OpenGL");
+
+    // There are no windows, so don't ask for a config that can draw to one.
+    let config_attribs = [
+        egl::RENDERABLE_TYPE,
+        egl::OPENGL_BIT,
+        egl::SURFACE_TYPE,
+        egl::PBUFFER_BIT,
+        egl::NONE,
+    ];
+    let config = egl
+        .choose_first_config(display, &config_attribs)
+        .expect("could not choose an EGL config")
+        .expect("no EGL config for OpenGL");
+    let attribs = [
+        egl::CONTEXT_MAJOR_VERSION,
+        4,
+        egl::CONTEXT_MINOR_VERSION,
+        3,
+        egl::CONTEXT_OPENGL_PROFILE_MASK,
+        egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+        egl::NONE,
+    ];
+    let context = egl
+        .create_context(display, config, None, &attribs)
+        .expect("no OpenGL 4.3 context");
+    egl.make_current(display, None, None, Some(context))
+        .expect("could not make the context current");
+
+    gl::load_with(|name| {
+        egl.get_proc_address(name)
+            .map_or(ptr::null(), |f| f as *const _)
+    });
+
+    Context {
+        egl,
+        display,
+        context,
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        let _ = self.egl.make_current(self.display, None, None, None);
+        let _ = self.egl.destroy_context(self.display, self.context);
+    }
+}
+
+/// Read the first `n` points of a vertex buffer back.
+pub unsafe fn read(vbo: &Vbo, n: usize) -> Vec<glm::Vec2> {
+    let mut data = vec![glm::vec2(0.0, 0.0); n];
+    vbo.bind();
+    gl::GetBufferSubData(
+        gl::ARRAY_BUFFER,
+        0,
+        gl_buf_size(&data),
+        gl_mut_ptr(&mut data),
+    );
+    data
+}
+
+/// Read the first `n` points of a storage buffer back.
+pub unsafe fn read_storage(ssbo: &Ssbo, n: usize) -> Vec<glm::Vec2> {
+    let mut data = vec![glm::vec2(0.0, 0.0); n];
+    ssbo.bind();
+    gl::GetBufferSubData(
+        gl::SHADER_STORAGE_BUFFER,
+        0,
+        gl_buf_size(&data),
+        gl_mut_ptr(&mut data),
+    );
+    data
+}
@@ -1,7 +1,12 @@
 //! # Text Renderer implementation.
-use crate::atlas::{Atlas, Outline};
-use crate::gpu::{Transform, self, shader::*, Texture, Vao, Vbo};
+use crate::atlas::{Atlas, ColorLayer, GlyphSpan, Outline};
+use crate::cache::TextCache;
+use crate::packed::{PackedOutline, Precision};
+use crate::shaping::{self, Glyph, ShapingOptions};
+use crate::gpu::{Transform, self, shader::*, Dibo, Ssbo, Texture, Vao, Vbo};
 use crate::spline::Rect;
+use std::collections::HashMap;
+use std::ops::Range;
 use std::sync::{Arc, RwLock};
 
 const TEX_SIZE: u32 = 4*4096;
@@ -17,6 +22,23 @@ pub struct TextRenderer {
     fill_mvp: UniformMat4,
     outline: Shader,
     outline_mvp: UniformMat4,
+    // The same, for packed outlines.
+    fill_packed: PackedPass,
+    outline_packed: PackedPass,
+    // And for glyphs instanced from a glyph buffer.
+    fill_glyphs: Shader,
+    fill_glyphs_mvp: UniformMat4,
+    outline_glyphs: Shader,
+    outline_glyphs_mvp: UniformMat4,
+}
+
+/// A shader that draws packed outlines, and its uniforms.
+struct PackedPass {
+    shader: Shader,
+    mvp: UniformMat4,
+    packing: UniformInt,
+    shared: UniformInt,
+    em: UniformFloat,
 }
 
 /// A text shader is just a shader that has some required uniforms
@@ -26,6 +48,8 @@ pub struct TextShader {
     u_mvp: UniformMat4,
     u_bbox: UniformVec4,
     u_texdims: UniformVec2i,
+    // Only shaders that can draw text in any colour have this. (See `Shader::color_blit`)
+    u_color: Option<UniformVec4>,
 }
 
 // Using the from/into to automatically retrieve the needed uniforms for broad classes of shaders.
@@ -40,23 +64,87 @@ impl From<Shader> for TextShader {
             let u_mvp = shader.uniform("mvp");
             let u_texdims = shader.uniform("tex_dims");
             let u_bbox = shader.uniform("bbox");
+            let u_color = shader.try_uniform("text_color");
 
             TextShader {
                 shader,
                 u_mvp,
                 u_bbox,
                 u_texdims,
+                u_color,
             }
         }
     }
 }
 
 /// A text element is "just the data": Vertex array and bounding box.
+/// It also remembers where each glyph is, so glyphs can be drawn and moved on their own.
 pub struct TextElement {
     pub bbox: Rect,
     vao: Vao<1>,
     vbo: Vbo,
     n: u32,
+    // The untransformed control points, and the glyphs in them.
+    ctrl_pts: Vec<(f32, f32)>,
+    glyphs: Vec<GlyphSpan>,
+    // Transform of each glyph. (See `transform_glyphs`)
+    transforms: Vec<glm::Mat3>,
+    // Box of the control points that belong to no glyph, like decorations. These don't move
+    // with the glyphs.
+    unclaimed: Option<Rect>,
+}
+
+/// Text in a colour font: A text element for each layer, painted bottom to top.
+pub struct ColorTextElement {
+    pub bbox: Rect,
+    layers: Vec<(TextElement, Option<glm::Vec4>)>,
+}
+
+/// Text drawn from a packed outline, which takes a third to half of the video memory of a
+/// `TextElement`. (See `packed`) It can't draw or move single glyphs.
+pub struct PackedTextElement {
+    pub bbox: Rect,
+    // The points are read from the storage buffer. The only attribute is the origin of each
+    // group, which is drawn as an instance.
+    vao: Vao<1>,
+    ssbo: Ssbo,
+    origins: Vbo,
+    commands: Dibo,
+    n: usize,
+    precision: Precision,
+    shared: bool,
+    em: f32,
+}
+
+/// The outlines of the glyphs of an atlas in video memory, shared by `GlyphTextElement`s.
+/// Each glyph is uploaded the first time some text uses it. A buffer belongs to the atlas it
+/// is first used with, and panics when used with another. (Including the same font with other
+/// variations, make a new buffer for those)
+pub struct GlyphBuffer {
+    ssbo: Ssbo,
+    // A copy of what is in the storage buffer, to upload it again when it grows.
+    ctrl_pts: Vec<(f32, f32)>,
+    // Where the control points of each glyph are in the buffer: (first, count)
+    ranges: HashMap<usize, (u32, u32)>,
+    uploaded: usize,
+    // Room in the storage buffer, in control points.
+    capacity: usize,
+    // `Atlas::font_hash` of the atlas the glyphs come from.
+    font: Option<u64>,
+}
+
+/// Text drawn by instancing the glyphs of a `GlyphBuffer`. Per glyph, it only has the place of
+/// its outline in the buffer and where to draw it, so updating the text uploads 28 bytes per
+/// glyph rather than 24 bytes per curve. Glyphs without ink (spaces) take nothing.
+pub struct GlyphTextElement {
+    pub bbox: Rect,
+    // The instance attribute: (x, y) of the glyph, and whether it is turned.
+    vao: Vao<1>,
+    instances: Vbo,
+    commands: Dibo,
+    n: usize,
+    // The atlas of the glyph buffer the text was made with.
+    font: u64,
 }
 
 /// The Arc-type allows animating by mutating the scene graph externally.
@@ -70,108 +158,169 @@ impl TextElement {
         transform: &Transform,
         text_shader: &TextShader,
     ) {
-        self.vao.bind();
-
-        //
-        // Some preliminary coordinate transform calculations.
-        //
-
-        // Scale is how many pixels tall the text is. (1em in pixels)
-        // Translation is position in pixel coordinates.
-        let Transform {
-            scale,
-            translation: (x, y),
-        } = *transform;
-
-        // Bounding box coordinates in pixels.
-        let bbox = self.bbox;
-        let (x0, x1) = ((scale * bbox.x0).floor(), (scale * bbox.x1).ceil());
-        let (y0, y1) = ((scale * bbox.y0).floor(), (scale * bbox.y1).ceil());
+        let vertices = 0..self.n as usize;
+        self.rasterize_part(renderer, transform, text_shader, vertices, self.bbox, None);
+    }
 
-        // The width and height (again, in pixels) of the quad.
-        let w = x1 - x0;
-        let h = y1 - y0;
+    /// Rasterize the text in a colour. The shader needs a `text_color` uniform, like
+    /// `Shader::color_blit`, otherwise the colour is ignored.
+    pub unsafe fn rasterize_colored(
+        &self,
+        renderer: &TextRenderer,
+        transform: &Transform,
+        text_shader: &TextShader,
+        color: glm::Vec4,
+    ) {
+        let vertices = 0..self.n as usize;
+        self.rasterize_part(renderer, transform, text_shader, vertices, self.bbox, Some(color));
+    }
 
-        // Issue: In theory the bbox might extend past the texture.
-        // This can be "fixed" by using a massive texture. This will invoke fragment processing of
-        // off-screen fragments, but it is actually not as costly as you would think, since
-        // the fragment shader is cheap, and the vertex processing (where the magic happens) has
-        // to be done anyways. Still far from ideal, but oh well.
+    /// Draw only some of the glyphs, for example to give them another colour by drawing them
+    /// again with a different shader. The glyphs are indices into `glyphs()`.
+    pub unsafe fn rasterize_glyphs(
+        &self,
+        renderer: &TextRenderer,
+        transform: &Transform,
+        text_shader: &TextShader,
+        glyphs: Range<usize>,
+    ) {
+        let spans = &self.glyphs[glyphs.clone()];
+        let (first, last) = match (spans.first(), spans.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return,
+        };
 
-        let tw = TEX_SIZE as f32;
-        let th = TEX_SIZE as f32;
+        let bbox = glyphs
+            .map(|i| self.glyph_bbox(i))
+            .reduce(|a, b| a.extend(b))
+            .unwrap_or(self.bbox);
 
-        // Projects the text element onto the texture.
-        let texture_projection = glm::ortho(4.0*x0, 4.0*x0 + tw, 4.0*y0, 4.0*y0 + th, 0.0, 100.0);
-        let texture_scale = glm::scaling(&glm::vec3(4.0*scale, 4.0*scale, 0.0));
-        let texture_mvp = texture_projection * texture_scale;
+        let vertices = first.ctrl.start..last.ctrl.end;
+        self.rasterize_part(renderer, transform, text_shader, vertices, bbox, None);
+    }
 
-        //
-        // Rasterize α-texture.
-        //
+    /// Rasterize the vertices in the given range, which lie within `bbox`.
+    unsafe fn rasterize_part(
+        &self,
+        renderer: &TextRenderer,
+        transform: &Transform,
+        text_shader: &TextShader,
+        vertices: Range<usize>,
+        bbox: Rect,
+        color: Option<glm::Vec4>,
+    ) {
+        composite(renderer, transform, text_shader, bbox, color, |texture_mvp| {
+            self.vao.bind();
 
-        // Save the old viewport.
-        let (vp_x, vp_y, vp_w, vp_h) = gpu::gl_viewport();
+            // Draw the fill of the glyphs.
+            renderer.fill.bind();
+            let u_mvp = &renderer.fill_mvp;
+            u_mvp.data(texture_mvp);
+            gl::DrawArrays(gl::TRIANGLES, vertices.start as i32, vertices.len() as i32);
 
-        // Look at a correctly sized box in the texture.
-        gl::BindFramebuffer(gl::FRAMEBUFFER, renderer.fbuf);
-        gl::Viewport(0, 0, tw as i32, th as i32);
+            // Finish the outline of the glyphs.
+            renderer.outline.bind();
+            let u_mvp = &renderer.outline_mvp;
+            u_mvp.data(texture_mvp);
+            gl::DrawArrays(gl::TRIANGLES, vertices.start as i32, vertices.len() as i32);
+        });
+    }
 
-        // Start with 100% transparent texture.
-        gl::ClearColor(0.0, 0.0, 0.0, 0.0);
-        gl::Clear(gl::COLOR_BUFFER_BIT);
+    pub unsafe fn update(&mut self, input: &str, atlas: &Atlas) {
+        self.update_outlined(atlas.outline(input));
+    }
 
-        // Enable XOR flipping. (Explanation in report)
-        gl::Enable(gl::COLOR_LOGIC_OP);
-        gl::LogicOp(gl::XOR);
+    /// Like `update`, but looks the outline up in a cache first. Labels that are set to the
+    /// same text over and over (or cycle between a few) don't have to be re-shaped.
+    pub unsafe fn update_cached(&mut self, input: &str, atlas: &Atlas, cache: &mut TextCache) {
+        let outline = cache.outline(input, atlas, &ShapingOptions::default());
+        self.set(&outline.ctrl_pts, outline.bbox, &outline.glyphs);
+    }
 
-        // Draw the fill of the glyphs.
-        renderer.fill.bind();
-        let u_mvp = &renderer.fill_mvp;
-        u_mvp.data(&texture_mvp);
-        gl::DrawArrays(gl::TRIANGLES, 0, self.n as i32);
+    /// Replace the contents with an already outlined shape.
+    pub unsafe fn update_outlined(&mut self, input: Outline) {
+        self.set(&input.ctrl_pts, input.bbox, &input.glyphs);
+    }
 
-        // Finish the outline of the glyphs.
-        renderer.outline.bind();
-        let u_mvp = &renderer.outline_mvp;
-        u_mvp.data(&texture_mvp);
-        gl::DrawArrays(gl::TRIANGLES, 0, self.n as i32);
+    /// Upload new contents. This re-uses the memory of the old contents where it can.
+    unsafe fn set(&mut self, ctrl_pts: &[(f32, f32)], bbox: Rect, glyphs: &[GlyphSpan]) {
+        self.vbo.data(ctrl_pts);
+        self.bbox = bbox;
+        self.n = ctrl_pts.len() as u32;
 
-        // Unbind framebuffer, and restore the old viewport.
-        gl::Disable(gl::COLOR_LOGIC_OP);
-        gl::Enable(gl::BLEND);
-        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
-        gl::Viewport(vp_x, vp_y, vp_w, vp_h);
+        self.ctrl_pts.clear();
+        self.ctrl_pts.extend_from_slice(ctrl_pts);
+        self.glyphs.clear();
+        self.glyphs.extend_from_slice(glyphs);
+        self.transforms.clear();
+        self.transforms.resize(glyphs.len(), glm::Mat3::identity());
+        self.unclaimed = unclaimed(ctrl_pts, glyphs);
+    }
 
-        //
-        // Draw a quad sampling the α-texture.
-        //
+    /// The glyphs of the text, in the order they were laid out. (Which is visual order, left
+    /// to right.) Positions are in units of 1em relative to the origin of the element, and
+    /// do not include the transforms set with `transform_glyphs`.
+    pub fn glyphs(&self) -> &[GlyphSpan] {
+        &self.glyphs
+    }
 
-        text_shader.shader.bind();
+    /// The glyph under the point (x, y), in units of 1em relative to the origin of the
+    /// element. To find the glyph under the cursor, subtract the translation of the element
+    /// from the cursor position and divide by its scale.
+    ///
+    /// A glyph is hit within its bounding box. Between glyphs, and on spaces (which have no
+    /// ink), the glyph with the closest box is hit, as long as the point is within the box of
+    /// the whole text. This works the same for text in any direction.
+    pub fn glyph_at(&self, x: f32, y: f32) -> Option<usize> {
+        if x < self.bbox.x0 || x > self.bbox.x1 || y < self.bbox.y0 || y > self.bbox.y1 {
+            return None;
+        }
+        closest((0..self.glyphs.len()).map(|i| self.glyph_bbox(i)), x, y)
+    }
 
-        // Submit data about the quad.
-        text_shader.u_bbox.data(x0, y0, x1, y1);
-        text_shader.u_texdims.data(w as i32, h as i32);
+    /// Transform the glyphs in a range with an affine transform, in units of 1em. The
+    /// transform replaces any earlier transform of these glyphs, so animations can set it
+    /// every frame. Use the pen position of a glyph to rotate or scale it about itself.
+    pub unsafe fn transform_glyphs(&mut self, glyphs: Range<usize>, transform: &glm::Mat3) {
+        for i in glyphs.clone() {
+            self.transforms[i] = *transform;
+        }
 
-        // Window projection.
-        let window_projection = glm::ortho(0.0, vp_w as f32, 0.0, vp_h as f32, 0.0, 100.0);
-        let model_matrix = glm::translation(&glm::vec3(x.floor(), y.floor(), 0.0));
-        let window_mvp = window_projection * model_matrix;
-        let u_mvp = &text_shader.u_mvp;
-        u_mvp.data(&window_mvp);
+        let spans = &self.glyphs[glyphs.clone()];
+        let vertices = match (spans.first(), spans.last()) {
+            (Some(first), Some(last)) => first.ctrl.start..last.ctrl.end,
+            _ => return,
+        };
 
-        // Bind the texture we just rasterized to.
-        renderer.tex.bind();
+        let moved: Vec<(f32, f32)> = self.ctrl_pts[vertices.clone()]
+            .iter()
+            .map(|&(x, y)| {
+                let p = transform * glm::vec3(x, y, 1.0);
+                (p.x, p.y)
+            })
+            .collect();
+        self.vbo.sub_data(vertices.start, &moved);
 
-        // Draw it on a quad.
-        gl::DrawArrays(gl::TRIANGLES, 0, 6);
+        self.bbox = (0..self.glyphs.len())
+            .map(|i| self.glyph_bbox(i))
+            .chain(self.unclaimed)
+            .reduce(|a, b| a.extend(b))
+            .unwrap_or(self.bbox);
     }
 
-    pub unsafe fn update(&mut self, input: &str, atlas: &Atlas) {
-        let Outline { ctrl_pts, bbox } = atlas.outline(input);
-        self.vbo.data(&ctrl_pts);
-        self.bbox = bbox;
-        self.n = ctrl_pts.len() as u32;
+    /// Bounding box of a glyph, after its transform.
+    fn glyph_bbox(&self, i: usize) -> Rect {
+        let Rect { x0, x1, y0, y1 } = self.glyphs[i].bbox;
+        let m = &self.transforms[i];
+
+        let corners = [(x0, y0), (x1, y0), (x0, y1), (x1, y1)]
+            .map(|(x, y)| m * glm::vec3(x, y, 1.0));
+        Rect {
+            x0: corners.iter().map(|p| p.x).fold(f32::INFINITY, f32::min),
+            x1: corners.iter().map(|p| p.x).fold(-f32::INFINITY, f32::max),
+            y0: corners.iter().map(|p| p.y).fold(f32::INFINITY, f32::min),
+            y1: corners.iter().map(|p| p.y).fold(-f32::INFINITY, f32::max),
+        }
     }
 
     /// Create a single "pseudo-glyph" out of stacked symbols.
@@ -204,11 +353,16 @@ impl TextElement {
 
         vao.attrib_ptr(0, 2, gl::FLOAT);
 
+        // The stack is a single glyph as far as anyone else is concerned.
         TextElement {
             bbox,
             vao,
             vbo,
             n: bot.ctrl_pts.len() as u32,
+            ctrl_pts: bot.ctrl_pts,
+            glyphs: Vec::new(),
+            transforms: Vec::new(),
+            unclaimed: None,
         }
     }
 
@@ -219,7 +373,11 @@ impl TextElement {
     }
 
     pub unsafe fn outlined(input: Outline) -> Self {
-        let Outline { ctrl_pts, bbox } = input;
+        let Outline {
+            ctrl_pts,
+            bbox,
+            glyphs,
+        } = input;
 
         let vao = Vao::<1>::gen();
         vao.enable_attrib_arrays();
@@ -234,8 +392,487 @@ impl TextElement {
             vao,
             vbo,
             n: ctrl_pts.len() as u32,
+            transforms: vec![glm::Mat3::identity(); glyphs.len()],
+            unclaimed: unclaimed(&ctrl_pts, &glyphs),
+            ctrl_pts,
+            glyphs,
+        }
+    }
+}
+
+impl ColorTextElement {
+    /// Outline the text with the given palette of the font. (See `Atlas::color_layers`)
+    pub unsafe fn new(input: &str, atlas: &Atlas, palette: usize) -> Self {
+        Self::layered(atlas.color_layers(input, palette))
+    }
+
+    /// Paint layers that were already outlined, like those of `Atlas::halo`.
+    pub unsafe fn layered(layers: Vec<ColorLayer>) -> Self {
+        let mut element = ColorTextElement {
+            bbox: Rect {
+                x0: 0.0,
+                x1: 0.0,
+                y0: 0.0,
+                y1: 0.0,
+            },
+            layers: Vec::new(),
+        };
+        element.update_layered(layers);
+        element
+    }
+
+    pub unsafe fn update(&mut self, input: &str, atlas: &Atlas, palette: usize) {
+        self.update_layered(atlas.color_layers(input, palette));
+    }
+
+    pub unsafe fn update_layered(&mut self, layers: Vec<ColorLayer>) {
+        if let Some(bbox) = layers.iter().map(|l| l.outline.bbox).reduce(|a, b| a.extend(b)) {
+            self.bbox = bbox;
+        }
+
+        // Re-use the buffers of the old layers.
+        let mut old = std::mem::take(&mut self.layers).into_iter();
+        for ColorLayer { outline, color } in layers {
+            let element = match old.next() {
+                Some((mut element, _)) => {
+                    element.update_outlined(outline);
+                    element
+                }
+                None => TextElement::outlined(outline),
+            };
+            self.layers.push((element, color));
         }
     }
+
+    /// Paint the layers. Layers without a colour of their own get the `foreground` colour.
+    /// The shader needs a `text_color` uniform, like `Shader::color_blit`.
+    pub unsafe fn rasterize(
+        &self,
+        renderer: &TextRenderer,
+        transform: &Transform,
+        text_shader: &TextShader,
+        foreground: glm::Vec4,
+    ) {
+        for (element, color) in &self.layers {
+            let color = color.unwrap_or(foreground);
+            element.rasterize_colored(renderer, transform, text_shader, color);
+        }
+    }
+}
+
+impl PackedTextElement {
+    pub unsafe fn new(input: &str, atlas: &Atlas, precision: Precision, shared: bool) -> Self {
+        let em = atlas.face.units_per_em();
+        Self::packed(PackedOutline::new(&atlas.outline(input), precision, shared, em))
+    }
+
+    pub unsafe fn packed(input: PackedOutline) -> Self {
+        let vao = Vao::<1>::gen();
+        vao.enable_attrib_arrays();
+
+        let origins = Vbo::gen();
+        origins.bind();
+        vao.attrib_ptr(0, 2, gl::FLOAT);
+        vao.attrib_divisor(0, 1);
+
+        let mut element = PackedTextElement {
+            bbox: input.bbox,
+            vao,
+            ssbo: Ssbo::gen(),
+            origins,
+            commands: Dibo::gen(),
+            n: 0,
+            precision: input.precision,
+            shared: input.shared,
+            em: input.em,
+        };
+        element.update_packed(input);
+        element
+    }
+
+    /// Outline the text again, with the same packing.
+    pub unsafe fn update(&mut self, input: &str, atlas: &Atlas) {
+        let outline = atlas.outline(input);
+        let em = atlas.face.units_per_em();
+        self.update_packed(PackedOutline::new(&outline, self.precision, self.shared, em));
+    }
+
+    pub unsafe fn update_packed(&mut self, input: PackedOutline) {
+        // One draw per group, with the origin of the group as its instance. (See
+        // `GlyphTextElement::update_shaped` for the layout of the commands)
+        let commands: Vec<[u32; 4]> = input
+            .groups
+            .iter()
+            .enumerate()
+            .map(|(i, g)| {
+                let count = 3 * g.curves.len() as u32;
+                [count, 1, 3 * g.curves.start as u32, i as u32]
+            })
+            .collect();
+        let origins: Vec<(f32, f32)> = input.groups.iter().map(|g| g.origin).collect();
+
+        self.ssbo.data(&input.words);
+        self.origins.data(&origins);
+        self.commands.data(&commands);
+        self.n = commands.len();
+        self.bbox = input.bbox;
+        self.precision = input.precision;
+        self.shared = input.shared;
+        self.em = input.em;
+    }
+
+    pub unsafe fn rasterize(
+        &self,
+        renderer: &TextRenderer,
+        transform: &Transform,
+        text_shader: &TextShader,
+    ) {
+        composite(renderer, transform, text_shader, self.bbox, None, |texture_mvp| {
+            self.vao.bind();
+            self.commands.bind();
+            self.ssbo.bind_base(0);
+
+            for pass in [&renderer.fill_packed, &renderer.outline_packed] {
+                pass.shader.bind();
+                pass.mvp.data(texture_mvp);
+                pass.packing.data(self.precision as i32);
+                pass.shared.data(self.shared as i32);
+                pass.em.data(self.em);
+                gl::MultiDrawArraysIndirect(gl::TRIANGLES, std::ptr::null(), self.n as i32, 0);
+            }
+        });
+    }
+}
+
+impl GlyphBuffer {
+    pub unsafe fn new() -> Self {
+        GlyphBuffer {
+            ssbo: Ssbo::gen(),
+            ctrl_pts: Vec::new(),
+            ranges: HashMap::new(),
+            uploaded: 0,
+            capacity: 0,
+            font: None,
+        }
+    }
+
+    /// Tie the buffer to the atlas if it is new, or check that it is the one it belongs to.
+    fn claim(&mut self, atlas: &Atlas) -> u64 {
+        let hash = atlas.font_hash();
+        let font = *self.font.get_or_insert(hash);
+        assert_eq!(font, hash, "the glyph buffer belongs to another atlas");
+        hash
+    }
+
+    /// Put the outline of a glyph in the buffer, if it isn't already.
+    /// Returns where its control points are. (See `upload`)
+    fn insert(&mut self, atlas: &Atlas, glyph_id: usize) -> (u32, u32) {
+        self.claim(atlas);
+        let ctrl_pts = &mut self.ctrl_pts;
+        *self.ranges.entry(glyph_id).or_insert_with(|| {
+            let first = ctrl_pts.len();
+            for curve in atlas.glyph(glyph_id).strokes() {
+                ctrl_pts.extend([curve.0, curve.1, curve.2].map(<(f32, f32)>::from));
+            }
+            (first as u32, (ctrl_pts.len() - first) as u32)
+        })
+    }
+
+    /// Upload the glyphs that were inserted since last time.
+    unsafe fn upload(&mut self) {
+        let n = self.ctrl_pts.len();
+        if n == self.uploaded {
+            return;
+        }
+
+        // Grow by doubling, so that text with new glyphs usually only uploads those.
+        if n > self.capacity {
+            self.capacity = usize::max(n, 2 * self.capacity);
+            self.ssbo.reserve::<(f32, f32)>(self.capacity);
+            self.uploaded = 0;
+        }
+        self.ssbo.sub_data(self.uploaded, &self.ctrl_pts[self.uploaded..]);
+        self.uploaded = n;
+    }
+
+    /// The number of glyphs in the buffer.
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Size of the outlines in video memory, in bytes. The buffer grows by doubling, so it
+    /// takes up to twice as much.
+    pub fn size(&self) -> usize {
+        std::mem::size_of_val(&self.ctrl_pts[..])
+    }
+}
+
+impl GlyphTextElement {
+    pub unsafe fn new(input: &str, atlas: &Atlas, buffer: &mut GlyphBuffer) -> Self {
+        Self::shaped(&shaping::shape(input, &atlas.face), atlas, buffer)
+    }
+
+    /// Draw glyphs that were already shaped, for example with `shaping::shape_with`.
+    pub unsafe fn shaped(glyphs: &[Glyph], atlas: &Atlas, buffer: &mut GlyphBuffer) -> Self {
+        let vao = Vao::<1>::gen();
+        vao.enable_attrib_arrays();
+
+        let instances = Vbo::gen();
+        instances.bind();
+        vao.attrib_ptr(0, 3, gl::FLOAT);
+        vao.attrib_divisor(0, 1);
+
+        let mut element = GlyphTextElement {
+            bbox: Rect {
+                x0: 0.0,
+                x1: 0.0,
+                y0: 0.0,
+                y1: 0.0,
+            },
+            vao,
+            instances,
+            commands: Dibo::gen(),
+            n: 0,
+            font: 0,
+        };
+        element.update_shaped(glyphs, atlas, buffer);
+        element
+    }
+
+    pub unsafe fn update(&mut self, input: &str, atlas: &Atlas, buffer: &mut GlyphBuffer) {
+        self.update_shaped(&shaping::shape(input, &atlas.face), atlas, buffer);
+    }
+
+    pub unsafe fn update_shaped(
+        &mut self,
+        glyphs: &[Glyph],
+        atlas: &Atlas,
+        buffer: &mut GlyphBuffer,
+    ) {
+        self.font = buffer.claim(atlas);
+        let mut instances: Vec<[f32; 3]> = Vec::with_capacity(glyphs.len());
+        // Layout of the commands of glDrawArraysIndirect: count, instance count, first vertex,
+        // and first instance.
+        let mut commands: Vec<[u32; 4]> = Vec::with_capacity(glyphs.len());
+        let mut bbox: Option<Rect> = None;
+
+        for g in glyphs {
+            let (first, count) = buffer.insert(atlas, g.glyph_id);
+            if count == 0 {
+                continue;
+            }
+
+            // The boxes in the face are those of the default instance, so use the outline.
+            let b = *atlas.glyph(g.glyph_id).bbox();
+            let ink = match g.rotated {
+                true => Rect {
+                    x0: b.y0 + g.x,
+                    x1: b.y1 + g.x,
+                    y0: -b.x1 + g.y,
+                    y1: -b.x0 + g.y,
+                },
+                false => Rect {
+                    x0: b.x0 + g.x,
+                    x1: b.x1 + g.x,
+                    y0: b.y0 + g.y,
+                    y1: b.y1 + g.y,
+                },
+            };
+            bbox = Some(bbox.map_or(ink, |bbox| bbox.extend(ink)));
+
+            commands.push([count, 1, first, instances.len() as u32]);
+            instances.push([g.x, g.y, g.rotated as u32 as f32]);
+        }
+
+        buffer.upload();
+        self.instances.data(&instances);
+        self.commands.data(&commands);
+        self.n = commands.len();
+        self.bbox = bbox.unwrap_or(Rect {
+            x0: 0.0,
+            x1: 0.0,
+            y0: 0.0,
+            y1: 0.0,
+        });
+    }
+
+    /// Rasterize the text. The buffer has to be the one the text was made with. (Panics if it
+    /// belongs to another atlas)
+    pub unsafe fn rasterize(
+        &self,
+        renderer: &TextRenderer,
+        buffer: &GlyphBuffer,
+        transform: &Transform,
+        text_shader: &TextShader,
+    ) {
+        assert_eq!(buffer.font, Some(self.font), "the text was made with another glyph buffer");
+        composite(renderer, transform, text_shader, self.bbox, None, |texture_mvp| {
+            self.vao.bind();
+            self.commands.bind();
+            buffer.ssbo.bind_base(0);
+
+            // Draw the fill of the glyphs.
+            renderer.fill_glyphs.bind();
+            renderer.fill_glyphs_mvp.data(texture_mvp);
+            gl::MultiDrawArraysIndirect(gl::TRIANGLES, std::ptr::null(), self.n as i32, 0);
+
+            // Finish the outline of the glyphs.
+            renderer.outline_glyphs.bind();
+            renderer.outline_glyphs_mvp.data(texture_mvp);
+            gl::MultiDrawArraysIndirect(gl::TRIANGLES, std::ptr::null(), self.n as i32, 0);
+        });
+    }
+}
+
+impl PackedPass {
+    unsafe fn new(shader: Shader) -> Self {
+        PackedPass {
+            mvp: shader.uniform("mvp"),
+            packing: shader.uniform("packing"),
+            shared: shader.uniform("shared_points"),
+            em: shader.uniform("em"),
+            shader,
+        }
+    }
+}
+
+/// The box of the control points outside the ranges of the glyphs.
+fn unclaimed(ctrl_pts: &[(f32, f32)], glyphs: &[GlyphSpan]) -> Option<Rect> {
+    let mut claimed = vec![false; ctrl_pts.len()];
+    for g in glyphs {
+        claimed[g.ctrl.clone()].fill(true);
+    }
+
+    ctrl_pts
+        .iter()
+        .zip(claimed)
+        .filter(|(_, claimed)| !claimed)
+        .map(|(&(x, y), _)| Rect {
+            x0: x,
+            x1: x,
+            y0: y,
+            y1: y,
+        })
+        .reduce(|a, b| a.extend(b))
+}
+
+/// The index of the box closest to (x, y). Boxes that contain the point are at distance 0, and
+/// the first of those wins.
+fn closest(boxes: impl Iterator<Item = Rect>, x: f32, y: f32) -> Option<usize> {
+    let distance = |b: &Rect| {
+        let dx = f32::max(b.x0 - x, 0.0).max(x - b.x1);
+        let dy = f32::max(b.y0 - y, 0.0).max(y - b.y1);
+        dx.hypot(dy)
+    };
+
+    boxes
+        .map(|b| distance(&b))
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+}
+
+/// Rasterize the α-texture of something within `bbox` with `draw`, which gets the projection
+/// onto the texture, and paint it with the text shader.
+unsafe fn composite(
+    renderer: &TextRenderer,
+    transform: &Transform,
+    text_shader: &TextShader,
+    bbox: Rect,
+    color: Option<glm::Vec4>,
+    draw: impl FnOnce(&glm::Mat4),
+) {
+    //
+    // Some preliminary coordinate transform calculations.
+    //
+
+    // Scale is how many pixels tall the text is. (1em in pixels)
+    // Translation is position in pixel coordinates.
+    let Transform {
+        scale,
+        translation: (x, y),
+    } = *transform;
+
+    // Bounding box coordinates in pixels.
+    let (x0, x1) = ((scale * bbox.x0).floor(), (scale * bbox.x1).ceil());
+    let (y0, y1) = ((scale * bbox.y0).floor(), (scale * bbox.y1).ceil());
+
+    // The width and height (again, in pixels) of the quad.
+    let w = x1 - x0;
+    let h = y1 - y0;
+
+    // Issue: In theory the bbox might extend past the texture.
+    // This can be "fixed" by using a massive texture. This will invoke fragment processing of
+    // off-screen fragments, but it is actually not as costly as you would think, since
+    // the fragment shader is cheap, and the vertex processing (where the magic happens) has
+    // to be done anyways. Still far from ideal, but oh well.
+
+    let tw = TEX_SIZE as f32;
+    let th = TEX_SIZE as f32;
+
+    // Projects the text element onto the texture.
+    let texture_projection = glm::ortho(4.0*x0, 4.0*x0 + tw, 4.0*y0, 4.0*y0 + th, 0.0, 100.0);
+    let texture_scale = glm::scaling(&glm::vec3(4.0*scale, 4.0*scale, 0.0));
+    let texture_mvp = texture_projection * texture_scale;
+
+    //
+    // Rasterize α-texture.
+    //
+
+    // Save the old viewport.
+    let (vp_x, vp_y, vp_w, vp_h) = gpu::gl_viewport();
+
+    // Look at a correctly sized box in the texture.
+    gl::BindFramebuffer(gl::FRAMEBUFFER, renderer.fbuf);
+    gl::Viewport(0, 0, tw as i32, th as i32);
+
+    // Start with 100% transparent texture.
+    gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+    gl::Clear(gl::COLOR_BUFFER_BIT);
+
+    // Enable XOR flipping. (Explanation in report)
+    gl::Enable(gl::COLOR_LOGIC_OP);
+    gl::LogicOp(gl::XOR);
+
+    // Draw the fill and the outline of the glyphs.
+    draw(&texture_mvp);
+
+    // Unbind framebuffer, and restore the old viewport.
+    gl::Disable(gl::COLOR_LOGIC_OP);
+    gl::Enable(gl::BLEND);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    gl::Viewport(vp_x, vp_y, vp_w, vp_h);
+
+    //
+    // Draw a quad sampling the α-texture.
+    //
+
+    text_shader.shader.bind();
+
+    if let (Some(u_color), Some(c)) = (&text_shader.u_color, color) {
+        u_color.data(c.x, c.y, c.z, c.w);
+    }
+
+    // Submit data about the quad.
+    text_shader.u_bbox.data(x0, y0, x1, y1);
+    text_shader.u_texdims.data(w as i32, h as i32);
+
+    // Window projection.
+    let window_projection = glm::ortho(0.0, vp_w as f32, 0.0, vp_h as f32, 0.0, 100.0);
+    let model_matrix = glm::translation(&glm::vec3(x.floor(), y.floor(), 0.0));
+    let window_mvp = window_projection * model_matrix;
+    let u_mvp = &text_shader.u_mvp;
+    u_mvp.data(&window_mvp);
+
+    // Bind the texture we just rasterized to.
+    renderer.tex.bind();
+
+    // Draw it on a quad.
+    gl::DrawArrays(gl::TRIANGLES, 0, 6);
 }
 
 pub struct TextRendererState {
@@ -254,6 +891,14 @@ impl TextRenderer {
         let outline = Shader::outline();
         let outline_mvp = fill.uniform("mvp");
 
+        let fill_packed = PackedPass::new(Shader::fill_packed());
+        let outline_packed = PackedPass::new(Shader::outline_packed());
+
+        let fill_glyphs = Shader::fill_glyphs();
+        let fill_glyphs_mvp = fill_glyphs.uniform("mvp");
+        let outline_glyphs = Shader::outline_glyphs();
+        let outline_glyphs_mvp = outline_glyphs.uniform("mvp");
+
         //
         // Set up α-texture. (See report for what this does)
         //
@@ -283,8 +928,184 @@ impl TextRenderer {
             fill_mvp,
             outline,
             outline_mvp,
+            fill_packed,
+            outline_packed,
+            fill_glyphs,
+            fill_glyphs_mvp,
+            outline_glyphs,
+            outline_glyphs_mvp,
             tex,
             fbuf,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x0: f32, y0: f32, x1: f32, y1: f32) -> Rect {
+        Rect { x0, x1, y0, y1 }
+    }
+
+    fn span(ctrl: Range<usize>, bbox: Rect) -> GlyphSpan {
+        GlyphSpan {
+            glyph_id: 0,
+            ctrl,
+            cluster: 0..1,
+            pen: (bbox.x0, 0.0),
+            advance: bbox.x1 - bbox.x0,
+            bbox,
+            vertical: false,
+        }
+    }
+
+    #[test]
+    fn decorations_are_unclaimed() {
+        // One glyph, then an underline below it.
+        let ctrl_pts = [(0.0, 0.0), (1.0, 1.0), (0.5, 0.5), (-0.2, -0.3), (1.2, -0.2), (0.0, -0.2)];
+        let glyphs = [span(0..3, rect(0.0, 0.0, 1.0, 1.0))];
+        let b = unclaimed(&ctrl_pts, &glyphs).unwrap();
+        assert_eq!((b.x0, b.y0, b.x1, b.y1), (-0.2, -0.3, 1.2, -0.2));
+
+        assert!(unclaimed(&ctrl_pts[..3], &glyphs).is_none());
+    }
+
+    #[test]
+    fn vertical_glyphs_are_hit_by_their_boxes() {
+        // A column of glyphs going down, all with the same pen x.
+        let boxes = [
+            rect(0.0, -1.0, 1.0, 0.0),
+            rect(0.0, -2.0, 1.0, -1.0),
+            rect(0.0, -3.0, 1.0, -2.0),
+        ];
+        assert_eq!(closest(boxes.into_iter(), 0.5, -0.5), Some(0));
+        assert_eq!(closest(boxes.into_iter(), 0.5, -2.5), Some(2));
+        assert_eq!(closest(boxes.into_iter(), 0.1, -1.5), Some(1));
+    }
+
+    #[test]
+    fn gaps_hit_the_closest_glyph() {
+        let boxes = [rect(0.0, 0.0, 1.0, 1.0), rect(2.0, 0.0, 3.0, 1.0)];
+        assert_eq!(closest(boxes.into_iter(), 1.2, 0.5), Some(0));
+        assert_eq!(closest(boxes.into_iter(), 1.8, 0.5), Some(1));
+        assert_eq!(closest(std::iter::empty(), 0.0, 0.0), None);
+    }
+
+    /// Rasterize an element into the α-texture of the renderer, and read that back.
+    unsafe fn alpha(
+        renderer: &TextRenderer,
+        bbox: Rect,
+        rasterize: impl FnOnce(&TextShader),
+    ) -> Vec<u8> {
+        let scale = 16.0;
+        rasterize(&Shader::simple_blit().into());
+
+        let w = 4 * ((scale * bbox.x1).ceil() - (scale * bbox.x0).floor()) as i32;
+        let h = 4 * ((scale * bbox.y1).ceil() - (scale * bbox.y0).floor()) as i32;
+        let mut pixels = vec![0u8; (w * h) as usize];
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, renderer.fbuf);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(0, 0, w, h, gl::RED, gl::UNSIGNED_BYTE, pixels.as_mut_ptr().cast());
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        pixels
+    }
+
+    #[test]
+    #[ignore = "needs OpenGL 4.3, see gpu::testing"]
+    fn packed_text_is_as_exact_far_from_the_origin() {
+        let _context = gpu::testing::context();
+        let atlas = Atlas::new(&crate::font::LM_ROMAN);
+        let em = atlas.face.units_per_em();
+
+        // The reference is the same text at full precision, near the origin. (Not a
+        // `TextElement`, which fans out its fill from elsewhere, so slivers on the baseline
+        // come out differently)
+        let near = atlas.outline("Packed glyphs");
+        let near_bbox = near.bbox;
+
+        // Far away, where half floats would be off by a lot. The shift is a whole number of
+        // pixels, so the α-texture should be the same.
+        let mut far = atlas.outline("Packed glyphs");
+        let (x, y) = (1000.0, -300.0);
+        for p in &mut far.ctrl_pts {
+            *p = (p.0 + x, p.1 + y);
+        }
+        for g in &mut far.glyphs {
+            g.pen = (g.pen.0 + x, g.pen.1 + y);
+        }
+        let b = far.bbox;
+        far.bbox = rect(b.x0 + x, b.y0 + y, b.x1 + x, b.y1 + y);
+
+        unsafe {
+            let renderer = TextRenderer::new();
+            let transform = Transform { scale: 16.0, translation: (0.0, 0.0) };
+
+            let packed = PackedOutline::new(&near, Precision::Full, false, em);
+            let element = PackedTextElement::packed(packed);
+            let expected = alpha(&renderer, near_bbox, |shader| {
+                element.rasterize(&renderer, &transform, shader)
+            });
+            assert!(expected.iter().any(|&a| a != 0));
+
+            for precision in [Precision::Full, Precision::Half, Precision::Fixed] {
+                for shared in [false, true] {
+                    let packed = PackedOutline::new(&far, precision, shared, em);
+                    let element = PackedTextElement::packed(packed);
+                    let pixels = alpha(&renderer, far.bbox, |shader| {
+                        element.rasterize(&renderer, &transform, shader)
+                    });
+
+                    // Rounding may move an edge by a sample here and there.
+                    let off = expected.iter().zip(&pixels).filter(|(a, b)| a != b).count();
+                    assert!(
+                        off * 100 < expected.len(),
+                        "{precision:?}, shared: {shared}, {off} of {} samples differ",
+                        expected.len()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs OpenGL 4.3, see gpu::testing"]
+    fn glyph_buffers_grow_by_doubling() {
+        let _context = gpu::testing::context();
+        let atlas = Atlas::new(&crate::font::LM_ROMAN);
+
+        unsafe {
+            let mut buffer = GlyphBuffer::new();
+            let mut text = GlyphTextElement::new("ab", &atlas, &mut buffer);
+            let capacity = buffer.capacity;
+            assert_eq!(capacity, buffer.ctrl_pts.len());
+
+            // New glyphs go after the old ones, and the storage doubles when they don't fit.
+            text.update("abc", &atlas, &mut buffer);
+            assert_eq!(buffer.capacity, 2 * capacity);
+            text.update("abcdefghij", &atlas, &mut buffer);
+            assert!(buffer.capacity >= buffer.ctrl_pts.len());
+
+            // Whatever was uploaded when, it is all there.
+            let n = buffer.ctrl_pts.len();
+            let stored = gpu::testing::read_storage(&buffer.ssbo, n);
+            let expected = buffer.ctrl_pts.iter().map(|&(x, y)| glm::vec2(x, y));
+            assert!(stored.into_iter().eq(expected));
+        }
+    }
+
+    #[test]
+    #[ignore = "needs OpenGL 4.3, see gpu::testing"]
+    #[should_panic(expected = "the glyph buffer belongs to another atlas")]
+    fn glyph_buffers_belong_to_one_atlas() {
+        let _context = gpu::testing::context();
+        let roman = Atlas::new(&crate::font::LM_ROMAN);
+        let italic = Atlas::new(&crate::font::LM_ROMAN_ITALIC);
+
+        unsafe {
+            let mut buffer = GlyphBuffer::new();
+            let _text = GlyphTextElement::new("roman", &roman, &mut buffer);
+            GlyphTextElement::new("italic", &italic, &mut buffer);
+        }
+    }
+}
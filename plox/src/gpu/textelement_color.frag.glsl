@@ -0,0 +1,23 @@
+#version 440
+
+in vec2 uv;
+out vec4 color;
+
+uniform sampler2D tex;
+uniform ivec2 tex_dims;
+uniform vec4 text_color;
+
+void main() {
+    int x = 4 * int(tex_dims.x * uv.x);
+    int y = 4 * int(tex_dims.y * uv.y);
+
+    float alpha = 0.0;
+    for (int i = 0; i < 4; i++) {
+        for (int j = 0; j < 4; j++) {
+            alpha += texelFetch(tex, ivec2(x + i, y + j), 0).r;
+        }
+    }
+    alpha /= 16.0;
+
+    color = vec4(text_color.rgb, text_color.a * clamp(alpha, 0.0, 1.0));
+}
@@ -13,8 +13,20 @@ pub mod svg;
 pub mod shaping;
 pub mod font;
 pub mod atlas;
+pub mod cache;
+pub mod color;
+pub mod metrics;
+pub mod variation;
+pub mod style;
+pub mod decoration;
+pub mod rich;
+pub mod path;
+pub mod packed;
+pub mod paragraph;
 pub mod polynomial;
 pub mod line;
+pub mod arrow;
+pub mod colormap;
 pub mod tesselate;
 pub mod gpu;
 
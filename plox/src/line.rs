@@ -1,7 +1,10 @@
-use crate::gpu::shader::{Shader, UniformFloat, UniformMat4};
-use crate::gpu::{self, Ibo, Vao, Vbo};
-use crate::tesselate::tesselate;
-use glm::Vec2;
+use crate::arrow::Arrows;
+use crate::gpu::shader::{Shader, UniformFloat, UniformInt, UniformMat4};
+use crate::gpu::text::{TextElement, TextRenderer, TextShader};
+use crate::gpu::{self, Ibo, Ssbo, Vao, Vbo, Transform};
+use crate::spline::Cubic;
+use crate::tesselate::{per_vertex, tesselate, Tesselation};
+use glm::{Vec2, Vec4};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Segment {
@@ -15,6 +18,18 @@ pub struct LinearSpline {
 
 pub struct LineRenderer {
     pub default_line_shader: LineShader,
+    /// Only available if the context supports compute shaders.
+    pub cubic_tesselator: Option<CubicTesselator>,
+}
+
+/// Tesselates cubic Bézier curves with a compute shader, writing straight into the vertex
+/// buffers of a `LineElement`. This saves sampling the curve on the CPU and uploading the
+/// result every time the curve changes.
+pub struct CubicTesselator {
+    shader: Shader,
+    u_width: UniformFloat,
+    u_segments: UniformInt,
+    ctrl: Ssbo,
 }
 
 pub struct LineShader {
@@ -39,12 +54,52 @@ impl From<Shader> for LineShader {
 }
 
 pub struct LineElement {
-    vao: Vao<2>,
+    vao: Vao<4>,
     pos_vbo: Vbo,
     uv_vbo: Vbo,
+    color_vbo: Vbo,
+    value_vbo: Vbo,
     ibo: Ibo,
-    n_segments: u32,
+    n_indices: u32,
     width: f32,
+    // Which input point each vertex belongs to.
+    src: Vec<usize>,
+    // Per-point attributes. These are kept around so they survive re-tesselation.
+    colors: Vec<Vec4>,
+    values: Vec<f32>,
+}
+
+/// Many polylines packed into shared buffers, so they can all be drawn in a single draw call.
+/// Useful for grids, ticks and other things that come in the hundreds. Each polyline has its own
+/// width and colour.
+pub struct LineBatch {
+    vao: Vao<4>,
+    pos_vbo: Vbo,
+    uv_vbo: Vbo,
+    color_vbo: Vbo,
+    value_vbo: Vbo,
+    ibo: Ibo,
+    n_indices: u32,
+    // The buffers are built on the CPU, and uploaded in one go.
+    mesh: BatchMesh,
+}
+
+/// The vertices of a `LineBatch`.
+#[derive(Default)]
+struct BatchMesh {
+    verts: Vec<Vec2>,
+    uvs: Vec<Vec2>,
+    colors: Vec<Vec4>,
+    values: Vec<f32>,
+    idx: Vec<u32>,
+}
+
+/// A line with arrowheads. The heads are filled outlines, so they are drawn by the text renderer
+/// to get the same anti-aliasing as everything else.
+pub struct ArrowElement {
+    line: LineElement,
+    heads: TextElement,
+    arrows: Arrows,
 }
 
 impl Segment {
@@ -89,7 +144,7 @@ impl LinearSpline {
 impl LineElement {
     pub unsafe fn rasterize(
         &self,
-        renderer: &LineRenderer,
+        _renderer: &LineRenderer,
         trans: glm::Vec2,
         line_shader: &LineShader,
     ) {
@@ -104,12 +159,12 @@ impl LineElement {
 
         line_shader.shader.bind();
 
-        renderer.default_line_shader.u_mvp.data(&(proj * model));
-        renderer.default_line_shader.u_width.data(self.width);
+        line_shader.u_mvp.data(&(proj * model));
+        line_shader.u_width.data(self.width);
 
         gl::DrawElements(
             gl::TRIANGLES,
-            self.n_segments as i32 * 6,
+            self.n_indices as i32,
             gl::UNSIGNED_INT,
             std::ptr::null(),
         );
@@ -127,11 +182,82 @@ impl LineElement {
     where
         S: Iterator<Item = &'a Segment>,
     {
-        let (vs, uvs, idx) = tesselate(segments, width);
-        self.pos_vbo.data(&vs);
+        // Degenerate input gives an empty tesselation, which simply draws nothing.
+        let Tesselation {
+            verts,
+            uvs,
+            idx,
+            src,
+        } = tesselate(segments, width);
+        self.pos_vbo.data(&verts);
         self.uv_vbo.data(&uvs);
         self.ibo.data(&idx);
-        self.n_segments = vs.len() as u32 / 2 - 1;
+        self.n_indices = idx.len() as u32;
+        self.src = src;
+        self.upload_attribs();
+    }
+
+    /// Set the colour of every point on the line. The colour is interpolated along the segments.
+    /// If there are fewer colours than points, the last colour is repeated.
+    pub unsafe fn set_colors(&mut self, colors: &[Vec4]) {
+        self.colors = colors.to_vec();
+        self.upload_attribs();
+    }
+
+    /// Set a scalar value for every point on the line, for example time or speed along a
+    /// trajectory. The values are normalized to the range [lo, hi] and looked up in the
+    /// colormap of a colormap shader. (See `Shader::colormap_line`) An empty range, like that
+    /// of a constant series, maps everything to the middle of the colormap.
+    pub unsafe fn set_values(&mut self, values: &[f32], range: (f32, f32)) {
+        self.values = normalize(values, range);
+        self.upload_attribs();
+    }
+
+    /// Tesselate a cubic Bézier curve, on the GPU if a tesselator is available, and otherwise
+    /// by sampling the curve on the CPU.
+    pub unsafe fn update_cubic(
+        &mut self,
+        cubic: &Cubic,
+        width: f32,
+        tesselator: Option<&CubicTesselator>,
+    ) {
+        let ctrl = [cubic.p0, cubic.p1, cubic.p2, cubic.p3];
+        if !ctrl.iter().all(|p| p.x.is_finite() && p.y.is_finite()) {
+            // Missing data, like a NaN point on a line. There is nothing to draw.
+            self.update(std::iter::empty(), width);
+            return;
+        }
+
+        // The shader needs a curve with a direction, and a width to draw. The tesselator on the
+        // CPU drops everything else.
+        let polygon: f32 = ctrl.windows(2).map(|p| (p[1] - p[0]).norm()).sum();
+        let drawable = polygon > 1e-4 && width.is_finite() && width > 0.0;
+
+        match tesselator {
+            Some(tesselator) if drawable => {
+                let n = tesselator.tesselate(cubic, width, &self.pos_vbo, &self.uv_vbo);
+                // A curve sampled at n+1 points is a line with n segments, and there is no
+                // difference in the index buffer.
+                let idx: Vec<u32> = (0..n as u32)
+                    .flat_map(|n| [2 * n, 2 * n + 2, 2 * n + 3, 2 * n, 2 * n + 3, 2 * n + 1])
+                    .collect();
+                self.ibo.data(&idx);
+                self.n_indices = idx.len() as u32;
+                self.src = (0..=n).flat_map(|k| [k, k]).collect();
+                self.upload_attribs();
+            }
+            _ => {
+                let spline = Segment::spline(&cubic.sample());
+                self.update(spline.segments(), width);
+            }
+        }
+    }
+
+    /// Expand the per-point attributes to the tesselated vertices, and upload them.
+    unsafe fn upload_attribs(&self) {
+        let black = glm::vec4(0.0, 0.0, 0.0, 1.0);
+        self.color_vbo.data(&per_vertex(&self.colors, &self.src, black));
+        self.value_vbo.data(&per_vertex(&self.values, &self.src, 0.0));
     }
 
     pub unsafe fn new<'a, S>(segments: S, width: f32) -> Self
@@ -149,15 +275,28 @@ impl LineElement {
         uv_vbo.bind();
         vao.attrib_ptr(1, 2, gl::FLOAT);
 
+        let color_vbo = Vbo::gen();
+        color_vbo.bind();
+        vao.attrib_ptr(2, 4, gl::FLOAT);
+
+        let value_vbo = Vbo::gen();
+        value_vbo.bind();
+        vao.attrib_ptr(3, 1, gl::FLOAT);
+
         let ibo = Ibo::gen();
 
         let mut li = LineElement {
             vao,
             pos_vbo,
             uv_vbo,
+            color_vbo,
+            value_vbo,
             ibo,
-            n_segments: 0,
+            n_indices: 0,
             width,
+            src: Vec::new(),
+            colors: Vec::new(),
+            values: Vec::new(),
         };
 
         li.update(segments, width);
@@ -166,11 +305,385 @@ impl LineElement {
     }
 }
 
+impl LineBatch {
+    pub unsafe fn new() -> Self {
+        let vao = Vao::gen();
+        vao.enable_attrib_arrays();
+
+        let pos_vbo = Vbo::gen();
+        pos_vbo.bind();
+        vao.attrib_ptr(0, 2, gl::FLOAT);
+
+        let uv_vbo = Vbo::gen();
+        uv_vbo.bind();
+        vao.attrib_ptr(1, 2, gl::FLOAT);
+
+        let color_vbo = Vbo::gen();
+        color_vbo.bind();
+        vao.attrib_ptr(2, 4, gl::FLOAT);
+
+        let value_vbo = Vbo::gen();
+        value_vbo.bind();
+        vao.attrib_ptr(3, 1, gl::FLOAT);
+
+        let ibo = Ibo::gen();
+
+        LineBatch {
+            vao,
+            pos_vbo,
+            uv_vbo,
+            color_vbo,
+            value_vbo,
+            ibo,
+            n_indices: 0,
+            mesh: BatchMesh::default(),
+        }
+    }
+
+    /// Add a polyline of a single colour to the batch.
+    /// Nothing is sent to the GPU before `upload` is called.
+    pub fn push(&mut self, pts: &[Vec2], width: f32, color: Vec4) {
+        self.mesh.push(pts, width, &[color], &[]);
+    }
+
+    /// Add a polyline with a colour for every point to the batch.
+    pub fn push_colored(&mut self, pts: &[Vec2], width: f32, colors: &[Vec4]) {
+        self.mesh.push(pts, width, colors, &[]);
+    }
+
+    /// Add a polyline with a value for every point to the batch, for a colormap shader. The
+    /// values are normalized to `range` like those of `LineElement::set_values`. Polylines
+    /// pushed without values are at the bottom of the colormap.
+    pub fn push_valued(&mut self, pts: &[Vec2], width: f32, values: &[f32], range: (f32, f32)) {
+        let black = glm::vec4(0.0, 0.0, 0.0, 1.0);
+        self.mesh.push(pts, width, &[black], &normalize(values, range));
+    }
+
+    /// Remove all the polylines from the batch.
+    pub fn clear(&mut self) {
+        self.mesh.clear();
+    }
+
+    /// Send the batch to the GPU.
+    pub unsafe fn upload(&mut self) {
+        let mesh = &self.mesh;
+        self.pos_vbo.data(&mesh.verts);
+        self.uv_vbo.data(&mesh.uvs);
+        self.color_vbo.data(&mesh.colors);
+        self.value_vbo.data(&mesh.values);
+        self.ibo.data(&mesh.idx);
+        self.n_indices = mesh.idx.len() as u32;
+    }
+}
+
+impl BatchMesh {
+    fn push(&mut self, pts: &[Vec2], width: f32, colors: &[Vec4], values: &[f32]) {
+        let spline = Segment::spline(pts);
+        let Tesselation {
+            verts,
+            uvs,
+            idx,
+            src,
+        } = tesselate(spline.segments(), width);
+
+        // The indices of the polyline are relative to its own vertices, so offset
+        // them past everything that is already in the batch.
+        let base = self.verts.len() as u32;
+        self.idx.extend(idx.into_iter().map(|i| base + i));
+
+        let black = glm::vec4(0.0, 0.0, 0.0, 1.0);
+        self.colors.extend(per_vertex(colors, &src, black));
+        self.values.extend(per_vertex(values, &src, 0.0));
+        self.verts.extend(verts);
+        self.uvs.extend(uvs);
+    }
+
+    fn clear(&mut self) {
+        self.verts.clear();
+        self.uvs.clear();
+        self.colors.clear();
+        self.values.clear();
+        self.idx.clear();
+    }
+}
+
+impl ArrowElement {
+    pub unsafe fn rasterize(
+        &self,
+        line_renderer: &LineRenderer,
+        text_renderer: &TextRenderer,
+        trans: Vec2,
+        line_shader: &LineShader,
+        text_shader: &TextShader,
+    ) {
+        self.line.rasterize(line_renderer, trans, line_shader);
+
+        // The heads are in the same (pixel) coordinates as the line.
+        if self.arrows.start.is_some() || self.arrows.end.is_some() {
+            let transform = Transform {
+                scale: 1.0,
+                translation: (trans.x, trans.y),
+            };
+            self.heads.rasterize(text_renderer, &transform, text_shader);
+        }
+    }
+
+    /// Update the arrow to follow the polyline `pts`. Works for sampled curves as well,
+    /// for example `Cubic::sample`.
+    pub unsafe fn update(&mut self, pts: &[Vec2], width: f32) {
+        let (pts, heads) = self.arrows.attach(pts, width);
+        let spline = Segment::spline(&pts);
+        self.line.update(spline.segments(), width);
+        self.heads.update_outlined(heads);
+    }
+
+    pub unsafe fn new(pts: &[Vec2], width: f32, arrows: Arrows) -> Self {
+        let (pts, heads) = arrows.attach(pts, width);
+        let spline = Segment::spline(&pts);
+        ArrowElement {
+            line: LineElement::new(spline.segments(), width),
+            heads: TextElement::outlined(heads),
+            arrows,
+        }
+    }
+
+    pub unsafe fn arrow(from: Vec2, to: Vec2, width: f32, arrows: Arrows) -> Self {
+        Self::new(&[from, to], width, arrows)
+    }
+}
+
+impl CubicTesselator {
+    /// Create a tesselator, if the context supports compute shaders (OpenGL 4.3).
+    pub unsafe fn new() -> Option<Self> {
+        if gpu::gl_version() < (4, 3) {
+            return None;
+        }
+
+        let shader = Shader::cubic_tesselation();
+        let u_width = shader.uniform("width");
+        let u_segments = shader.uniform("segments");
+
+        Some(CubicTesselator {
+            shader,
+            u_width,
+            u_segments,
+            ctrl: Ssbo::gen(),
+        })
+    }
+
+    /// Number of segments to split the curve into. This uses the length of the control polygon,
+    /// which bounds the length of the curve, to get segments no longer than what
+    /// `Cubic::sample` produces.
+    pub fn segments(cubic: &Cubic) -> usize {
+        const EPS: f32 = 3.0;
+        let l = (cubic.p1 - cubic.p0).norm()
+            + (cubic.p2 - cubic.p1).norm()
+            + (cubic.p3 - cubic.p2).norm();
+        usize::clamp((l / EPS).ceil() as usize, 8, 1024)
+    }
+
+    /// Tesselate the curve into the given position- and uv-buffers, and return the number of
+    /// segments. The buffers are reallocated to fit.
+    pub unsafe fn tesselate(&self, cubic: &Cubic, width: f32, pos: &Vbo, uv: &Vbo) -> usize {
+        let n = Self::segments(cubic);
+
+        self.ctrl.data(&[cubic.p0, cubic.p1, cubic.p2, cubic.p3]);
+        pos.reserve::<Vec2>(2 * (n + 1));
+        uv.reserve::<Vec2>(2 * (n + 1));
+
+        self.ctrl.bind_base(0);
+        pos.bind_base(1);
+        uv.bind_base(2);
+
+        self.shader.bind();
+        self.u_width.data(width);
+        self.u_segments.data(n as i32);
+
+        // One invocation per sample point, in work groups of 64.
+        gl::DispatchCompute((n as u32 + 1).div_ceil(64), 1, 1);
+
+        // Make sure the vertices are written before they are drawn.
+        gl::MemoryBarrier(gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+
+        n
+    }
+}
+
 impl LineRenderer {
     pub unsafe fn new() -> Self {
         let shader = Shader::line();
         LineRenderer {
             default_line_shader: shader.into(),
+            cubic_tesselator: CubicTesselator::new(),
+        }
+    }
+
+    /// Draw every polyline in a batch with a single draw call.
+    pub unsafe fn draw_batch(&self, batch: &LineBatch, trans: Vec2, line_shader: &LineShader) {
+        batch.vao.bind();
+        batch.ibo.bind();
+
+        let (x, y) = (trans.x, trans.y);
+
+        let (.., vp_w, vp_h) = gpu::gl_viewport();
+        let proj = glm::ortho(0.0, vp_w as f32, 0.0, vp_h as f32, 0.0, 100.0);
+        let model = glm::translation(&glm::vec3(x.floor(), y.floor(), 0.0));
+
+        line_shader.shader.bind();
+
+        // The width uniform cancels out of the anti-aliasing in the line shaders, so the
+        // polylines can have different widths, even though they share the uniform.
+        line_shader.u_mvp.data(&(proj * model));
+        line_shader.u_width.data(1.0);
+
+        gl::DrawElements(
+            gl::TRIANGLES,
+            batch.n_indices as i32,
+            gl::UNSIGNED_INT,
+            std::ptr::null(),
+        );
+    }
+}
+
+/// Map values in `[lo, hi]` to `[0, 1]`. (See `LineElement::set_values`)
+fn normalize(values: &[f32], (lo, hi): (f32, f32)) -> Vec<f32> {
+    let range = hi - lo;
+    if range.abs() < f32::EPSILON {
+        vec![0.5; values.len()]
+    } else {
+        values.iter().map(|v| (v - lo) / range).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::testing;
+    use crate::tesselate::tesselate;
+
+    #[test]
+    fn batches_offset_the_indices_of_each_polyline() {
+        let v = glm::vec2;
+        let (red, blue) = (glm::vec4(1.0, 0.0, 0.0, 1.0), glm::vec4(0.0, 0.0, 1.0, 1.0));
+        let mut mesh = BatchMesh::default();
+
+        let first = [v(0.0, 0.0), v(10.0, 0.0)];
+        mesh.push(&first, 1.0, &[red], &[]);
+        let n = mesh.verts.len();
+        let m = mesh.idx.len();
+        assert!(mesh.idx.iter().all(|&i| (i as usize) < n));
+
+        // The second polyline has its own vertices, after the first.
+        let second = [v(0.0, 5.0), v(10.0, 5.0), v(20.0, 10.0)];
+        mesh.push(&second, 2.0, &[red, blue, blue], &[0.0, 0.5, 1.0]);
+        let single = tesselate(Segment::spline(&second).segments(), 2.0);
+        assert_eq!(mesh.verts.len(), n + single.verts.len());
+        let offset: Vec<u32> = single.idx.iter().map(|i| i + n as u32).collect();
+        assert_eq!(mesh.idx[m..], offset);
+
+        // Colours and values per vertex, from the point each vertex belongs to.
+        assert!(mesh.colors[..n].iter().all(|&c| c == red));
+        assert_eq!(mesh.colors[n..], per_vertex(&[red, blue, blue], &single.src, red));
+        assert_eq!(mesh.values[..n], vec![0.0; n]);
+        assert_eq!(mesh.values[n..], per_vertex(&[0.0, 0.5, 1.0], &single.src, 0.0));
+        assert_eq!(mesh.uvs.len(), mesh.verts.len());
+
+        mesh.clear();
+        assert!(mesh.verts.is_empty() && mesh.uvs.is_empty() && mesh.idx.is_empty());
+        assert!(mesh.colors.is_empty() && mesh.values.is_empty());
+        mesh.push(&first, 1.0, &[red], &[]);
+        assert_eq!((mesh.verts.len(), mesh.idx.len()), (n, m));
+    }
+
+    #[test]
+    fn values_are_normalized() {
+        assert_eq!(normalize(&[1.0, 2.0, 3.0], (1.0, 3.0)), [0.0, 0.5, 1.0]);
+        assert_eq!(normalize(&[4.0, 4.0], (4.0, 4.0)), [0.5, 0.5]);
+    }
+
+    fn cubics() -> Vec<Cubic> {
+        let v = glm::vec2;
+        vec![
+            // Straight, with evenly spaced control points.
+            Cubic::pts(v(0.0, 0.0), v(100.0, 0.0), v(200.0, 0.0), v(300.0, 0.0)),
+            // A gentle S.
+            Cubic::pts(v(0.0, 0.0), v(150.0, 100.0), v(150.0, -100.0), v(300.0, 0.0)),
+            // An arch.
+            Cubic::pts(v(-50.0, 20.0), v(-50.0, 300.0), v(250.0, 300.0), v(250.0, 20.0)),
+            // Coinciding control points at the ends.
+            Cubic::pts(v(0.0, 0.0), v(0.0, 0.0), v(200.0, 200.0), v(200.0, 200.0)),
+        ]
+    }
+
+    /// The compute shader against the tesselator on the CPU, fed the same sample points. The
+    /// shader puts the vertices on the exact normals of the curve, and the CPU mitres the
+    /// chords, so they agree up to a small fraction of the width.
+    #[test]
+    #[ignore = "needs OpenGL 4.3, see gpu::testing"]
+    fn gpu_tesselation_matches_cpu() {
+        let _context = testing::context();
+        let width = 2.0;
+
+        unsafe {
+            let tesselator = CubicTesselator::new().expect("no compute shaders");
+            let (pos, uv) = (Vbo::gen(), Vbo::gen());
+
+            for (c, cubic) in cubics().iter().enumerate() {
+                let n = tesselator.tesselate(cubic, width, &pos, &uv);
+                gl::MemoryBarrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+                let verts = testing::read(&pos, 2 * (n + 1));
+                let uvs = testing::read(&uv, 2 * (n + 1));
+
+                let points: Vec<Vec2> = (0..=n).map(|i| cubic.r(i as f32 / n as f32)).collect();
+                let cpu = tesselate(Segment::spline(&points).segments(), width);
+
+                assert_eq!(verts.len(), cpu.verts.len(), "cubic {}", c);
+                for (i, (a, b)) in verts.iter().zip(&cpu.verts).enumerate() {
+                    let d = (a - b).norm();
+                    assert!(d < 0.05 * width, "cubic {}, vertex {}: {} vs {}", c, i, a, b);
+                }
+                // Both go by arc length, even where the curve speeds up or slows down.
+                for (i, (a, b)) in uvs.iter().zip(&cpu.uvs).enumerate() {
+                    assert_eq!(a.y, b.y);
+                    assert!((a.x - b.x).abs() < 1e-4, "cubic {}, uv {}: {} vs {}", c, i, a, b);
+                }
+            }
+        }
+    }
+
+    /// Curves the shader can't draw go to the CPU, which draws nothing, like for a NaN point on
+    /// a line.
+    #[test]
+    #[ignore = "needs OpenGL 4.3, see gpu::testing"]
+    fn gpu_tesselation_of_degenerate_curves_is_empty() {
+        let _context = testing::context();
+        let v = glm::vec2;
+        let nan = f32::NAN;
+
+        unsafe {
+            let tesselator = CubicTesselator::new().expect("no compute shaders");
+            let mut line = LineElement::line(v(0.0, 0.0), v(1.0, 1.0), 1.0);
+
+            let p = v(3.0, 4.0);
+            let degenerate = [
+                (Cubic::pts(v(0.0, 0.0), v(nan, 1.0), v(2.0, 0.0), v(3.0, 1.0)), 1.0),
+                (Cubic::pts(v(nan, nan), v(nan, nan), v(nan, nan), v(nan, nan)), 1.0),
+                (Cubic::pts(v(0.0, 0.0), v(1.0, 1.0), v(2.0, 0.0), v(3.0, f32::INFINITY)), 1.0),
+                (Cubic::pts(p, p, p, p), 1.0),
+                (Cubic::pts(v(0.0, 0.0), v(1.0, 1.0), v(2.0, 0.0), v(3.0, 1.0)), 0.0),
+                (Cubic::pts(v(0.0, 0.0), v(1.0, 1.0), v(2.0, 0.0), v(3.0, 1.0)), nan),
+            ];
+            for (i, (cubic, width)) in degenerate.iter().enumerate() {
+                line.update_cubic(cubic, *width, Some(&tesselator));
+                assert_eq!(line.n_indices, 0, "curve {}", i);
+                assert!(line.src.is_empty(), "curve {}", i);
+            }
+
+            // And a proper curve still goes to the GPU.
+            let cubic = Cubic::pts(v(0.0, 0.0), v(10.0, 10.0), v(20.0, 0.0), v(30.0, 10.0));
+            line.update_cubic(&cubic, 1.0, Some(&tesselator));
+            let n = CubicTesselator::segments(&cubic) as u32;
+            assert_eq!(line.n_indices, 6 * n);
         }
     }
 }
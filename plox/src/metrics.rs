@@ -0,0 +1,261 @@
+//! Font and text metrics.
+//!
+//! Everything is normalised to units of 1em, like the outlines in the atlas, so the metrics of
+//! different faces can be mixed freely. Vertical positions are relative to the baseline, with
+//! the y-axis pointing up.
+use crate::shaping::Glyph;
+use crate::spline::Rect;
+use rustybuzz::Face;
+
+/// Vertical metrics of a face.
+#[derive(Debug, Clone, Copy)]
+pub struct FontMetrics {
+    /// Height of the highest point of the face above the baseline.
+    pub ascender: f32,
+    /// Position of the lowest point of the face. (Negative, it is below the baseline.)
+    pub descender: f32,
+    /// Extra space the font wants between the descender of one line and the ascender of the
+    /// next.
+    pub line_gap: f32,
+    /// Height of the lowercase letters.
+    pub x_height: f32,
+    /// Height of the uppercase letters.
+    pub cap_height: f32,
+    pub underline: LineMetrics,
+    pub strikeout: LineMetrics,
+}
+
+/// Position and thickness of a decoration line, like underline or strikeout.
+#[derive(Debug, Clone, Copy)]
+pub struct LineMetrics {
+    /// Height of the centre of the line.
+    pub position: f32,
+    pub thickness: f32,
+}
+
+/// Everything needed to place a string before it is drawn. (See `Atlas::measure`)
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub advance: f32,
+    /// Bounding box of the ink, relative to the origin on the baseline.
+    pub ink: Rect,
+    /// The box the text takes up in a line: From the origin to the advance, and from the
+    /// descender to the ascender of the face. Unlike the ink, this does not depend on which
+    /// letters are in the string, so labels measured by it line up.
+    pub line: Rect,
+    /// Distance from the top of the line box down to the baseline.
+    pub baseline: f32,
+    pub font: FontMetrics,
+}
+
+/// Metrics of a shaped run of glyphs.
+#[derive(Debug, Clone, Copy)]
+pub struct RunMetrics {
+    /// How far the pen moves over the run. This is what should be used to place things after
+    /// each other, not the ink.
+    pub advance: f32,
+    /// Bounding box of the ink of the run.
+    pub ink: Rect,
+}
+
+impl FontMetrics {
+    pub fn new(face: &Face) -> Self {
+        let em = face.units_per_em() as f32;
+        let norm = |v: i16| v as f32 / em;
+
+        // Fonts don't have to specify all of these. Measure the glyphs if they don't.
+        let glyph_height = |c: char| {
+            face.glyph_index(c)
+                .and_then(|id| face.glyph_bounding_box(id))
+                .map(|b| norm(b.y_max))
+        };
+
+        let ascender = norm(face.ascender());
+        let x_height = face
+            .x_height()
+            .map(norm)
+            .or_else(|| glyph_height('x'))
+            .unwrap_or(0.5 * ascender);
+        let cap_height = face
+            .capital_height()
+            .map(norm)
+            .or_else(|| glyph_height('H'))
+            .unwrap_or(0.7 * ascender);
+
+        // The position of decorations in the font tables is the top of the line. Centres are
+        // easier to work with.
+        let line = |m: ttf::LineMetrics| LineMetrics {
+            position: norm(m.position) - 0.5 * norm(m.thickness),
+            thickness: norm(m.thickness),
+        };
+
+        let underline = face.underline_metrics().map(line).unwrap_or(LineMetrics {
+            position: -0.1,
+            thickness: 0.05,
+        });
+
+        // Halfway up the lowercase letters, like LaTeX' \sout.
+        let strikeout = face.strikeout_metrics().map(line).unwrap_or(LineMetrics {
+            position: 0.5 * x_height,
+            thickness: underline.thickness,
+        });
+
+        FontMetrics {
+            ascender,
+            descender: norm(face.descender()),
+            line_gap: norm(face.line_gap()),
+            x_height,
+            cap_height,
+            underline,
+            strikeout,
+        }
+    }
+
+    /// Distance between the baselines of two lines of text.
+    pub fn line_height(&self) -> f32 {
+        self.ascender - self.descender + self.line_gap
+    }
+
+    /// The metrics of text of size `size`, instead of 1em.
+    pub fn scale(&self, size: f32) -> Self {
+        let line = |m: LineMetrics| LineMetrics {
+            position: size * m.position,
+            thickness: size * m.thickness,
+        };
+
+        FontMetrics {
+            ascender: size * self.ascender,
+            descender: size * self.descender,
+            line_gap: size * self.line_gap,
+            x_height: size * self.x_height,
+            cap_height: size * self.cap_height,
+            underline: line(self.underline),
+            strikeout: line(self.strikeout),
+        }
+    }
+}
+
+impl Measurement {
+    /// Measure a run of glyphs set in a face with the given metrics, at a size.
+    pub fn new(run: &RunMetrics, font: &FontMetrics, size: f32) -> Self {
+        let font = font.scale(size);
+        let advance = size * run.advance;
+
+        Measurement {
+            advance,
+            ink: Rect {
+                x0: size * run.ink.x0,
+                x1: size * run.ink.x1,
+                y0: size * run.ink.y0,
+                y1: size * run.ink.y1,
+            },
+            line: Rect {
+                x0: 0.0,
+                x1: advance,
+                y0: font.descender,
+                y1: font.ascender,
+            },
+            baseline: font.ascender,
+            font,
+        }
+    }
+}
+
+impl RunMetrics {
+    pub fn new(glyphs: &[Glyph]) -> Self {
+        let ink = glyphs
+            .iter()
+            .map(|g| Rect {
+                x0: g.bbox.x0 + g.x,
+                x1: g.bbox.x1 + g.x,
+                y0: g.bbox.y0 + g.y,
+                y1: g.bbox.y1 + g.y,
+            })
+            .reduce(|a, b| a.extend(b))
+            .unwrap_or(Rect {
+                x0: 0.0,
+                x1: 0.0,
+                y0: 0.0,
+                y1: 0.0,
+            });
+
+        RunMetrics {
+            advance: advance(glyphs),
+            ink,
+        }
+    }
+}
+
+/// Total advance of a run of glyphs.
+pub fn advance(glyphs: &[Glyph]) -> f32 {
+    glyphs.iter().map(|g| g.advance).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::LM_ROMAN;
+    use crate::shaping;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn latin_modern_roman() {
+        assert_eq!(LM_ROMAN.units_per_em(), 1000);
+        let font = FontMetrics::new(&LM_ROMAN);
+        assert!(close(font.ascender, 1.127));
+        assert!(close(font.descender, -0.29));
+        assert!(close(font.line_gap, 0.0));
+        assert!(close(font.x_height, 0.431));
+        assert!(close(font.cap_height, 0.683));
+        assert!(close(font.line_height(), 1.417));
+    }
+
+    #[test]
+    fn decorations_are_at_their_centre() {
+        let font = FontMetrics::new(&LM_ROMAN);
+
+        // The font has the top of the line.
+        let underline = LM_ROMAN.underline_metrics().unwrap();
+        let top = underline.position as f32 / 1000.0;
+        let thickness = underline.thickness as f32 / 1000.0;
+        assert!(close(font.underline.thickness, thickness));
+        assert!(close(font.underline.position, top - 0.5 * thickness));
+        assert!(close(font.underline.position, -0.146));
+    }
+
+    #[test]
+    fn scale() {
+        let font = FontMetrics::new(&LM_ROMAN);
+        let big = font.scale(3.0);
+        assert!(close(big.ascender, 3.0 * font.ascender));
+        assert!(close(big.descender, 3.0 * font.descender));
+        assert!(close(big.x_height, 3.0 * font.x_height));
+        assert!(close(big.cap_height, 3.0 * font.cap_height));
+        assert!(close(big.underline.position, 3.0 * font.underline.position));
+        assert!(close(
+            big.strikeout.thickness,
+            3.0 * font.strikeout.thickness
+        ));
+        assert!(close(big.line_height(), 3.0 * font.line_height()));
+    }
+
+    #[test]
+    fn runs() {
+        let glyphs = shaping::shape("Hx", &LM_ROMAN);
+        let run = RunMetrics::new(&glyphs);
+        assert_eq!(run.advance, advance(&glyphs));
+        assert!(close(run.advance, glyphs[0].advance + glyphs[1].advance));
+
+        // The ink of both letters, which sit on the baseline.
+        assert!(close(run.ink.x0, glyphs[0].bbox.x0));
+        assert!(close(run.ink.x1, glyphs[1].x + glyphs[1].bbox.x1));
+        assert!(close(run.ink.y0, 0.0));
+        assert!(close(run.ink.y1, 0.683));
+
+        let empty = RunMetrics::new(&[]);
+        assert_eq!((empty.advance, empty.ink.x0, empty.ink.x1), (0.0, 0.0, 0.0));
+    }
+}
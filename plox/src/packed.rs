@@ -0,0 +1,350 @@
+//! Packed glyph geometry.
+//!
+//! An outline is three full `(f32, f32)` points per quadratic, which adds up for large
+//! documents. A packed outline stores the points in 32 bits each (half floats, or i16 in font
+//! units), and can share the end point of each curve with the start of the next. The vertex
+//! shaders of `PackedTextElement` decode them again.
+//!
+//! The points are relative to the origin of their group, which is the pen position for the
+//! curves of a glyph. The origins are stored separately at full precision, so the packed
+//! numbers stay small, and text far from the origin of the outline is as exact as text near it.
+//! Curves that belong to no glyph (decorations, say) are grouped by how close they are, and
+//! long ones are split.
+//!
+//! Shared end points turn each run of connected curves into a chain of points, where curve `i`
+//! is points `2i`, `2i + 1` and `2i + 2`. The chain jumps from one contour to the next via
+//! the origin of the group, with two straight curves, and the chains of the groups are joined
+//! the same way. The jumps enclose no area, and the fill pass fans out all triangles from the
+//! origin of the group anyway, so they don't show up in the XOR fill.
+use crate::atlas::Outline;
+use crate::spline::Rect;
+use half::f16;
+use std::ops::Range;
+
+/// Packed points are at most this far from the origin of their group, in em. Half floats are
+/// exact to about 1/500em in this range.
+const REACH: f32 = 8.0;
+
+/// How the points of a packed outline are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    /// Two f32, like an unpacked outline.
+    Full = 0,
+    /// Two half floats.
+    Half = 1,
+    /// Two i16 in font units, which is as exact as the font.
+    Fixed = 2,
+}
+
+/// Curves with points relative to the same origin.
+#[derive(Debug, Clone)]
+pub struct Group {
+    /// The range of curves, including jumps.
+    pub curves: Range<usize>,
+    pub origin: (f32, f32),
+}
+
+#[derive(Debug, Clone)]
+pub struct PackedOutline {
+    /// The points, one or two words each depending on the precision.
+    pub words: Vec<u32>,
+    pub precision: Precision,
+    /// Whether consecutive curves share their end points.
+    pub shared: bool,
+    pub groups: Vec<Group>,
+    /// Units per em of `Fixed` points.
+    pub em: f32,
+    pub bbox: Rect,
+}
+
+type Curve = [(f32, f32); 3];
+
+impl PackedOutline {
+    /// Pack an outline. `units_per_em` is the size of the em of `Fixed` points, use that of
+    /// the face the outline comes from.
+    pub fn new(outline: &Outline, precision: Precision, shared: bool, units_per_em: i32) -> Self {
+        let em = units_per_em as f32;
+        let reach = f32::min(REACH, i16::MAX as f32 / em);
+
+        // Which glyph each curve belongs to, if any.
+        let mut owners = vec![None; outline.ctrl_pts.len() / 3];
+        for (i, g) in outline.glyphs.iter().enumerate() {
+            owners[g.ctrl.start / 3..g.ctrl.end / 3].fill(Some(i));
+        }
+
+        // The curves of a glyph are relative to its pen position, unless they stick out too
+        // far. Other curves are relative to the start of the first curve of their group.
+        // (Groups are the glyph of the curves, if any, the origin and the curves)
+        type Run = (Option<usize>, (f32, f32), Vec<Curve>);
+        let mut groups: Vec<Run> = Vec::new();
+        let curves = outline
+            .ctrl_pts
+            .chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2]]);
+        for (curve, owner) in curves.zip(owners) {
+            for piece in fit(curve, reach) {
+                let near = |o: (f32, f32)| extent(&piece, o) <= reach;
+                match groups.last_mut() {
+                    Some((o, origin, curves)) if *o == owner && near(*origin) => {
+                        curves.push(piece)
+                    }
+                    _ => {
+                        let origin = match owner.map(|i| outline.glyphs[i].pen) {
+                            Some(pen) if near(pen) => pen,
+                            _ => piece[0],
+                        };
+                        groups.push((owner, origin, vec![piece]));
+                    }
+                }
+            }
+        }
+
+        let o = (0.0, 0.0);
+        let mut points: Vec<(f32, f32)> = Vec::with_capacity(outline.ctrl_pts.len());
+        let mut packed: Vec<Group> = Vec::with_capacity(groups.len());
+        let mut n = 0;
+        // The end of the chain, relative to the origin of the group it is in.
+        let mut last: Option<(f32, f32)> = None;
+
+        for (_, origin, curves) in groups {
+            if let (true, Some(l), Some(group)) = (shared, last, packed.last_mut()) {
+                // Go back to the origin of the previous group, which is where this one starts.
+                if l != o {
+                    points.extend([mid(l, o), o]);
+                    n += 1;
+                    group.curves.end = n;
+                }
+                last = Some(o);
+            }
+
+            let first = n;
+            for curve in curves {
+                let [p0, c, p2] = curve.map(|p| (p.0 - origin.0, p.1 - origin.1));
+                if !shared {
+                    points.extend([p0, c, p2]);
+                    n += 1;
+                    continue;
+                }
+
+                match last {
+                    None => points.push(p0),
+                    Some(l) if l == p0 => {}
+                    Some(l) if l == o => {
+                        points.extend([mid(o, p0), p0]);
+                        n += 1;
+                    }
+                    Some(l) => {
+                        // Jump to the next contour through the origin.
+                        points.extend([mid(l, o), o, mid(o, p0), p0]);
+                        n += 2;
+                    }
+                }
+                points.extend([c, p2]);
+                n += 1;
+                last = Some(p2);
+            }
+
+            packed.push(Group {
+                curves: first..n,
+                origin,
+            });
+        }
+
+        let mut words = Vec::with_capacity(points.len() * 2);
+        for (x, y) in points {
+            match precision {
+                Precision::Full => words.extend([x.to_bits(), y.to_bits()]),
+                Precision::Half => {
+                    let (x, y) = (f16::from_f32(x).to_bits(), f16::from_f32(y).to_bits());
+                    // unpackHalf2x16 takes the first component from the low bits.
+                    words.push(x as u32 | (y as u32) << 16);
+                }
+                Precision::Fixed => {
+                    let q = |v: f32| (v * em).round() as i16 as u16 as u32;
+                    words.push(q(x) | q(y) << 16);
+                }
+            }
+        }
+
+        PackedOutline {
+            words,
+            precision,
+            shared,
+            groups: packed,
+            em,
+            bbox: outline.bbox,
+        }
+    }
+
+    /// The number of curves to draw, including the jumps between contours.
+    pub fn curves(&self) -> usize {
+        self.groups.last().map_or(0, |g| g.curves.end)
+    }
+
+    /// Size of the packed points and origins in bytes.
+    pub fn size(&self) -> usize {
+        4 * self.words.len() + 8 * self.groups.len()
+    }
+}
+
+/// Split a curve in halves until each piece is within `reach` of its start.
+fn fit(curve: Curve, reach: f32) -> Vec<Curve> {
+    // (Curves that go off to infinity can't be split to fit.)
+    let e = extent(&curve, curve[0]);
+    if e <= reach || !e.is_finite() {
+        return vec![curve];
+    }
+
+    let [p0, p1, p2] = curve;
+    let (a, b) = (mid(p0, p1), mid(p1, p2));
+    let m = mid(a, b);
+    let mut pieces = fit([p0, a, m], reach);
+    pieces.extend(fit([m, b, p2], reach));
+    pieces
+}
+
+/// How far the points of a curve are from `origin`, along either axis.
+fn extent(curve: &Curve, origin: (f32, f32)) -> f32 {
+    curve
+        .iter()
+        .map(|p| f32::max((p.0 - origin.0).abs(), (p.1 - origin.1).abs()))
+        .fold(0.0, f32::max)
+}
+
+fn mid(a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    (0.5 * (a.0 + b.0), 0.5 * (a.1 + b.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atlas::Atlas;
+    use crate::font::LM_ROMAN;
+
+    /// Decode the curves like the vertex shaders do.
+    fn decode(packed: &PackedOutline) -> Vec<Curve> {
+        let point = |i: usize| match packed.precision {
+            Precision::Full => (
+                f32::from_bits(packed.words[2 * i]),
+                f32::from_bits(packed.words[2 * i + 1]),
+            ),
+            Precision::Half => {
+                let w = packed.words[i];
+                let h = |b: u32| f16::from_bits(b as u16).to_f32();
+                (h(w & 0xffff), h(w >> 16))
+            }
+            Precision::Fixed => {
+                let w = packed.words[i];
+                let q = |b: u32| b as u16 as i16 as f32 / packed.em;
+                (q(w & 0xffff), q(w >> 16))
+            }
+        };
+
+        let mut curves = Vec::new();
+        for group in &packed.groups {
+            let (x, y) = group.origin;
+            for i in group.curves.clone() {
+                let first = if packed.shared { 2 * i } else { 3 * i };
+                curves.push([0, 1, 2].map(|j| {
+                    let p = point(first + j);
+                    (x + p.0, y + p.1)
+                }));
+            }
+        }
+        curves
+    }
+
+    /// The largest distance between the curves of the outline and the decoded ones, which
+    /// have to come in the same order, with jumps in between.
+    fn error(outline: &Outline, packed: &PackedOutline) -> f32 {
+        let decoded = decode(packed);
+        assert_eq!(decoded.len(), packed.curves());
+        let distance = |a: &Curve, b: &Curve| {
+            let d = |i: usize| f32::max((a[i].0 - b[i].0).abs(), (a[i].1 - b[i].1).abs());
+            d(0).max(d(1)).max(d(2))
+        };
+
+        let mut max = 0.0;
+        let mut decoded = decoded.iter();
+        for c in outline.ctrl_pts.chunks_exact(3) {
+            let c = [c[0], c[1], c[2]];
+            let d = decoded
+                .by_ref()
+                .map(|d| distance(&c, d))
+                .find(|&d| d < 0.01)
+                .expect("curve is missing");
+            max = f32::max(max, d);
+        }
+        max
+    }
+
+    fn far_away(mut outline: Outline) -> Outline {
+        let (x, y) = (1000.0, -300.0);
+        for p in &mut outline.ctrl_pts {
+            *p = (p.0 + x, p.1 + y);
+        }
+        for g in &mut outline.glyphs {
+            g.pen = (g.pen.0 + x, g.pen.1 + y);
+        }
+        outline
+    }
+
+    #[test]
+    fn points_are_as_exact_far_from_the_origin() {
+        let atlas = Atlas::new(&LM_ROMAN);
+        let em = atlas.face.units_per_em();
+        let outline = far_away(atlas.outline("Packed glyphs"));
+
+        for shared in [false, true] {
+            let full = PackedOutline::new(&outline, Precision::Full, shared, em);
+            assert!(error(&outline, &full) < 1e-4);
+
+            let half = PackedOutline::new(&outline, Precision::Half, shared, em);
+            assert!(error(&outline, &half) < 2e-3);
+
+            // Rounded to font units, but no further.
+            let fixed = PackedOutline::new(&outline, Precision::Fixed, shared, em);
+            assert!(error(&outline, &fixed) <= 0.5 / em as f32 + 1e-4);
+        }
+
+        let shared = PackedOutline::new(&outline, Precision::Half, true, em);
+        let unshared = PackedOutline::new(&outline, Precision::Half, false, em);
+        assert!(shared.size() < unshared.size());
+    }
+
+    #[test]
+    fn long_curves_are_split() {
+        // A rule, like an underline, of 100em.
+        let (x0, x1, y0, y1) = (0.0, 100.0, -0.1, -0.05);
+        let line = |a: (f32, f32), b: (f32, f32)| [a, mid(a, b), b];
+        let ctrl_pts = [
+            line((x0, y0), (x1, y0)),
+            line((x1, y0), (x1, y1)),
+            line((x1, y1), (x0, y1)),
+            line((x0, y1), (x0, y0)),
+        ]
+        .concat();
+        let outline = Outline {
+            ctrl_pts,
+            bbox: Rect { x0, x1, y0, y1 },
+            glyphs: Vec::new(),
+        };
+
+        let em = 2048;
+        let packed = PackedOutline::new(&outline, Precision::Fixed, false, em);
+        assert!(packed.groups.len() > 1);
+        for curve in decode(&packed) {
+            for (x, y) in [curve[0], curve[2]] {
+                let on = |v: f32| (v - y0).abs() < 1e-3 || (v - y1).abs() < 1e-3;
+                assert!(x > x0 - 1e-3 && x < x1 + 1e-3 && on(y));
+            }
+        }
+
+        // The rule is still all there.
+        let length: f32 = decode(&packed)
+            .iter()
+            .map(|c| (c[2].0 - c[0].0).abs())
+            .sum();
+        assert!((length - 200.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,468 @@
+//! Paragraph layout.
+//!
+//! Breaks text into lines that fit a given width, and aligns them. Break opportunities come
+//! from the Unicode line breaking algorithm (UAX #14), and lines are filled greedily, which is
+//! what browsers do and good enough for captions and slides. (Knuth-Plass can come later.)
+//!
+//! All lengths are in units of 1em, like everything else that comes out of the atlas.
+use crate::atlas::{Atlas, Outline};
+use crate::metrics::advance;
+use crate::shaping::{self, Glyph, ShapingOptions};
+use crate::spline::Rect;
+use std::ops::Range;
+use unicode_linebreak::{linebreaks, BreakOpportunity};
+
+/// How the lines are placed horizontally within the width of the paragraph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+    /// Stretch the spaces so every line but the last of each paragraph fills the full width.
+    Justify,
+}
+
+/// Finds the places where a word may be hyphenated.
+///
+/// There is no hyphenation built in, since the dictionaries are large. The `hyphenation`
+/// crate plugs in with a closure:
+/// `move |word: &str| dictionary.hyphenate(word).breaks`
+pub trait Hyphenate {
+    /// Byte offsets into `word` where it may be broken with a hyphen.
+    fn breaks(&self, word: &str) -> Vec<usize>;
+}
+
+impl<F: Fn(&str) -> Vec<usize>> Hyphenate for F {
+    fn breaks(&self, word: &str) -> Vec<usize> {
+        self(word)
+    }
+}
+
+/// Layout settings for a paragraph.
+pub struct Paragraph {
+    width: f32,
+    align: Align,
+    line_spacing: f32,
+    options: ShapingOptions,
+    hyphenator: Option<Box<dyn Hyphenate>>,
+}
+
+/// A laid out paragraph.
+#[derive(Debug)]
+pub struct Layout {
+    pub outline: Outline,
+    pub lines: Vec<LineBox>,
+}
+
+/// A single line of a laid out paragraph.
+#[derive(Debug, Clone)]
+pub struct LineBox {
+    /// The byte range of the text on this line, including trailing whitespace.
+    pub range: Range<usize>,
+    /// Whether the line ends in an inserted hyphen.
+    pub hyphenated: bool,
+    /// Height of the baseline. The first line has its baseline at 0, and the following
+    /// lines go downwards.
+    pub baseline: f32,
+    /// The box of the line, from the descender to the ascender of the font, and spanning the
+    /// glyphs horizontally.
+    pub bbox: Rect,
+}
+
+/// A line, before it is shaped and positioned.
+struct Line {
+    range: Range<usize>,
+    hyphenated: bool,
+    // Last line of the paragraph. These are not justified.
+    last: bool,
+}
+
+impl Paragraph {
+    /// A paragraph that breaks lines at the given width. With an infinite width, lines are
+    /// only broken at newlines, and aligned to the widest line.
+    pub fn new(width: f32) -> Self {
+        Paragraph {
+            width,
+            align: Align::Left,
+            line_spacing: 1.0,
+            options: ShapingOptions::default(),
+            hyphenator: None,
+        }
+    }
+
+    pub fn align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// Line spacing as a multiple of the line height of the font, which is the distance
+    /// from the descender to the ascender plus the line gap.
+    pub fn line_spacing(mut self, spacing: f32) -> Self {
+        self.line_spacing = spacing;
+        self
+    }
+
+    /// Shaping options for the text. Panics if the direction is vertical, paragraphs of
+    /// vertical text are not supported.
+    pub fn options(mut self, options: ShapingOptions) -> Self {
+        assert!(!options.is_vertical(), "paragraphs of vertical text are not supported");
+        self.options = options;
+        self
+    }
+
+    /// Hyphenate words that don't fit on a line.
+    pub fn hyphenate(mut self, hyphenator: impl Hyphenate + 'static) -> Self {
+        self.hyphenator = Some(Box::new(hyphenator));
+        self
+    }
+
+    /// Break, shape and align the text, and outline it with the atlas.
+    pub fn layout(&self, text: &str, atlas: &Atlas) -> Layout {
+        let lines = self.break_lines(text, atlas);
+
+        let metrics = atlas.metrics();
+        let height = self.line_spacing * metrics.line_height();
+
+        // Shape every line first, the alignment of an infinitely wide paragraph depends on
+        // the widest line.
+        let shaped: Vec<(Vec<Glyph>, f32)> = lines
+            .iter()
+            .map(|line| {
+                let glyphs = self.shape_line(text, line, atlas);
+                let width = advance(&glyphs);
+                (glyphs, width)
+            })
+            .collect();
+
+        let width = if self.width.is_finite() {
+            self.width
+        } else {
+            shaped.iter().map(|(_, w)| *w).fold(0.0, f32::max)
+        };
+
+        let mut glyphs = Vec::new();
+        let mut boxes = Vec::with_capacity(lines.len());
+
+        for (i, (line, (mut line_glyphs, w))) in lines.into_iter().zip(shaped).enumerate() {
+            let baseline = -(i as f32) * height;
+            let slack = f32::max(width - w, 0.0);
+
+            let (offset, w) = match self.align {
+                Align::Left => (0.0, w),
+                Align::Right => (slack, w),
+                Align::Center => (0.5 * slack, w),
+                Align::Justify if line.last => (0.0, w),
+                Align::Justify => {
+                    justify(&mut line_glyphs, text, slack);
+                    (0.0, w + slack)
+                }
+            };
+
+            for g in &mut line_glyphs {
+                g.x += offset;
+                g.y += baseline;
+            }
+            glyphs.extend(line_glyphs);
+
+            boxes.push(LineBox {
+                range: line.range,
+                hyphenated: line.hyphenated,
+                baseline,
+                bbox: Rect {
+                    x0: offset,
+                    x1: offset + w,
+                    y0: baseline + metrics.descender,
+                    y1: baseline + metrics.ascender,
+                },
+            });
+        }
+
+        Layout {
+            outline: atlas.outline_glyphs(text, &glyphs),
+            lines: boxes,
+        }
+    }
+
+    /// Greedily fill lines with as many words as fit.
+    ///
+    /// The text is shaped once, and lines are measured by adding up the advances of the
+    /// clusters on them. This misses kerning and ligatures across the ends of lines, which
+    /// only matter when a line fits within a hair. (The lines are shaped again on their own
+    /// for the layout.)
+    fn break_lines(&self, text: &str, atlas: &Atlas) -> Vec<Line> {
+        let glyphs = shaping::shape_with(text, &atlas.face, &self.options);
+        let prefix = prefix_advances(text, &glyphs);
+        let hyphen = advance(&shaping::shape_with("-", &atlas.face, &self.options));
+
+        let fits = |range: Range<usize>, hyphenated: bool| {
+            let end = range.start + text[range.clone()].trim_end().len();
+            let hyphen = if hyphenated { hyphen } else { 0.0 };
+            prefix[end] - prefix[range.start] + hyphen <= self.width
+        };
+
+        let mut lines = Vec::new();
+        let mut start = 0;
+        // The last break opportunity on the current line, and the start of the current word.
+        let mut fit: Option<usize> = None;
+        let mut word = 0;
+
+        for (end, opportunity) in linebreaks(text) {
+            // Break the line until the rest fits, or it can't be broken any further.
+            while start < end && !fits(start..end, false) {
+                if let Some(cut) = self.hyphen_break(text, start, word..end, &fits) {
+                    lines.push(Line {
+                        range: start..cut,
+                        hyphenated: true,
+                        last: false,
+                    });
+                    start = cut;
+                    fit = None;
+                    continue;
+                }
+
+                match fit {
+                    Some(f) if f > start => {
+                        lines.push(Line {
+                            range: start..f,
+                            hyphenated: false,
+                            last: false,
+                        });
+                        start = f;
+                        fit = None;
+                    }
+                    // A single word that is wider than the paragraph. Let it stick out.
+                    _ => break,
+                }
+            }
+
+            if opportunity == BreakOpportunity::Mandatory {
+                lines.push(Line {
+                    range: start..end,
+                    hyphenated: false,
+                    last: true,
+                });
+                start = end;
+                fit = None;
+            } else {
+                fit = Some(end);
+            }
+            word = end;
+        }
+
+        lines
+    }
+
+    /// Find the longest hyphenated prefix of `word` that still fits on the line starting at
+    /// `start`. Returns the byte offset to break at. The line may start within the word, when
+    /// it was hyphenated before, but the hyphenator always gets the whole word.
+    fn hyphen_break(
+        &self,
+        text: &str,
+        start: usize,
+        word: Range<usize>,
+        fits: &impl Fn(Range<usize>, bool) -> bool,
+    ) -> Option<usize> {
+        let hyphenator = self.hyphenator.as_ref()?;
+        let trimmed = text[word.clone()].trim_end();
+
+        let mut breaks = hyphenator.breaks(trimmed);
+        breaks.sort_unstable();
+
+        breaks
+            .into_iter()
+            .rev()
+            .map(|b| word.start + b)
+            .filter(|&cut| cut > start && cut < word.start + trimmed.len())
+            .filter(|&cut| text.is_char_boundary(cut))
+            .find(|&cut| fits(start..cut, true))
+    }
+
+    /// Shape a single line, without its trailing whitespace. The clusters of the glyphs refer
+    /// to the full text.
+    fn shape_line(&self, text: &str, line: &Line, atlas: &Atlas) -> Vec<Glyph> {
+        let content = text[line.range.clone()].trim_end();
+        let mut glyphs = if line.hyphenated {
+            shaping::shape_with(format!("{}-", content), &atlas.face, &self.options)
+        } else {
+            shaping::shape_with(content, &atlas.face, &self.options)
+        };
+
+        for g in &mut glyphs {
+            // The hyphen belongs to where the line was broken.
+            g.cluster = line.range.start + g.cluster.min(content.len());
+        }
+        glyphs
+    }
+}
+
+/// The advance of the text before each byte offset, so `prefix[b] - prefix[a]` is the width
+/// of `text[a..b]`. Each glyph counts at the start of its cluster.
+fn prefix_advances(text: &str, glyphs: &[Glyph]) -> Vec<f32> {
+    let mut prefix = vec![0.0; text.len() + 1];
+    for g in glyphs {
+        prefix[g.cluster + 1] += g.advance;
+    }
+    for i in 1..prefix.len() {
+        prefix[i] += prefix[i - 1];
+    }
+    prefix
+}
+
+/// Distribute `slack` evenly over the spaces in a line.
+fn justify(glyphs: &mut [Glyph], text: &str, slack: f32) {
+    let is_space = |g: &Glyph| text[g.cluster..].starts_with(' ');
+    let spaces = glyphs.iter().filter(|g| is_space(g)).count();
+    if spaces == 0 {
+        return;
+    }
+
+    let extra = slack / spaces as f32;
+    let mut shift = 0.0;
+    for g in glyphs {
+        g.x += shift;
+        if is_space(g) {
+            shift += extra;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::LM_ROMAN;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn width(text: &str) -> f32 {
+        advance(&shaping::shape(text, &LM_ROMAN))
+    }
+
+    fn lines<'t>(paragraph: &Paragraph, text: &'t str) -> Vec<&'t str> {
+        let atlas = Atlas::new(&LM_ROMAN);
+        paragraph
+            .break_lines(text, &atlas)
+            .into_iter()
+            .map(|line| &text[line.range])
+            .collect()
+    }
+
+    #[test]
+    fn infinite_width_breaks_at_newlines() {
+        let p = Paragraph::new(f32::INFINITY);
+        let text = "a long first line\nand a second\n\nafter an empty one";
+        assert_eq!(
+            lines(&p, text),
+            ["a long first line\n", "and a second\n", "\n", "after an empty one"]
+        );
+
+        let atlas = Atlas::new(&LM_ROMAN);
+        let last: Vec<bool> = p.break_lines(text, &atlas).iter().map(|l| l.last).collect();
+        assert_eq!(last, [true, true, true, true]);
+    }
+
+    #[test]
+    fn lines_are_filled_greedily() {
+        let text = "the quick brown fox jumps over the lazy dog";
+        let w = width("the quick brown");
+        let p = Paragraph::new(w);
+        let broken = lines(&p, text);
+        assert_eq!(broken, ["the quick brown ", "fox jumps over ", "the lazy dog"]);
+
+        // Every line fits, and would not fit with the next word.
+        for pair in broken.windows(2) {
+            assert!(width(pair[0].trim_end()) <= w);
+            let next = pair[1].split(' ').next().unwrap();
+            assert!(width(&format!("{}{}", pair[0], next)) > w);
+        }
+        assert_eq!(lines(&p, "").len(), 0);
+    }
+
+    #[test]
+    fn long_words_stick_out() {
+        let p = Paragraph::new(width("ab"));
+        assert_eq!(lines(&p, "a incomprehensibilities b"), ["a ", "incomprehensibilities ", "b"]);
+    }
+
+    #[test]
+    fn hyphenation_gets_whole_words() {
+        let word = "incomprehensibilities";
+        // Syllables: in-com-pre-hen-si-bil-i-ties
+        let syllables = vec![2, 5, 8, 11, 13, 16, 17];
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let hyphenator = {
+            let seen = seen.clone();
+            move |word: &str| {
+                seen.borrow_mut().push(word.to_string());
+                if word == "incomprehensibilities" {
+                    syllables.clone()
+                } else {
+                    vec![]
+                }
+            }
+        };
+
+        let w = width("incompre-");
+        let p = Paragraph::new(w).hyphenate(hyphenator);
+        let atlas = Atlas::new(&LM_ROMAN);
+        let text = format!("{} end", word);
+        let broken = p.break_lines(&text, &atlas);
+
+        let ranges: Vec<&str> = broken.iter().map(|l| &text[l.range.clone()]).collect();
+        assert_eq!(ranges, ["incompre", "hensibili", "ties end"]);
+        let hyphenated: Vec<bool> = broken.iter().map(|l| l.hyphenated).collect();
+        assert_eq!(hyphenated, [true, true, false]);
+        for line in &ranges[..2] {
+            assert!(width(&format!("{}-", line)) <= w);
+        }
+
+        // The hyphenator never sees a fragment of the word.
+        assert!(seen.borrow().iter().all(|w| w == word || w == "end"));
+    }
+
+    #[test]
+    fn justify_spreads_slack_over_spaces() {
+        let text = "a b c";
+        let mut glyphs: Vec<Glyph> = (0..text.len())
+            .map(|i| Glyph {
+                glyph_id: 1,
+                bbox: Rect {
+                    x0: 0.0,
+                    x1: 0.0,
+                    y0: 0.0,
+                    y1: 0.0,
+                },
+                x: i as f32,
+                y: 0.0,
+                advance: 1.0,
+                cluster: i,
+                rotated: false,
+                vertical: false,
+            })
+            .collect();
+
+        justify(&mut glyphs, text, 2.0);
+        let x: Vec<f32> = glyphs.iter().map(|g| g.x).collect();
+        assert_eq!(x, [0.0, 1.0, 3.0, 4.0, 6.0]);
+
+        // Without spaces, there is nothing to stretch.
+        let mut word = glyphs[..1].to_vec();
+        justify(&mut word, "a", 2.0);
+        assert_eq!(word[0].x, 0.0);
+    }
+
+    #[test]
+    fn justified_lines_fill_the_width() {
+        let w = 10.0;
+        let p = Paragraph::new(w).align(Align::Justify);
+        let atlas = Atlas::new(&LM_ROMAN);
+        let text = "the quick brown fox jumps over the lazy dog and runs away";
+        let layout = p.layout(text, &atlas);
+        assert!(layout.lines.len() > 1);
+
+        let (last, full) = layout.lines.split_last().unwrap();
+        for line in full {
+            assert!((line.bbox.x1 - line.bbox.x0 - w).abs() < 1e-4);
+        }
+        assert!(last.bbox.x1 - last.bbox.x0 < w);
+    }
+}
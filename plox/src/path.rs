@@ -0,0 +1,278 @@
+//! Text along a path.
+//!
+//! For labelling curves: The glyphs are laid out on a straight baseline as usual, and then
+//! each is moved to the point of the path at the arc length of its centre, and rotated to the
+//! tangent there. Glyphs are moved as a whole, so they keep their shape (and their outlines
+//! stay exact), but long glyphs on tight bends will stick out a little.
+//!
+//! The path is in the coordinate system of the text, i. e. in units of 1em.
+use crate::atlas::{Atlas, Outline};
+use crate::line::LinearSpline;
+use crate::paragraph::Align;
+use crate::shaping::ShapingOptions;
+use crate::spline::{Cubic, Rect, Spline};
+use glm::Vec2;
+
+/// Curves are flattened into this many segments to measure their length.
+const SEGMENTS: usize = 64;
+
+/// A path for text to follow, measured by arc length.
+#[derive(Debug, Clone)]
+pub struct TextPath {
+    points: Vec<Vec2>,
+    // Arc length at each point.
+    lengths: Vec<f32>,
+}
+
+/// How text is put on a path.
+#[derive(Debug, Clone)]
+pub struct OnPath {
+    align: Align,
+    offset: f32,
+    options: ShapingOptions,
+}
+
+impl TextPath {
+    /// A path through some points. A path of less than two points, or of points that all
+    /// coincide, has no length and no direction, and `OnPath` puts nothing on it.
+    pub fn new(points: Vec<Vec2>) -> Self {
+        let mut lengths = Vec::with_capacity(points.len());
+        let mut length = 0.0;
+        if !points.is_empty() {
+            lengths.push(length);
+        }
+        for w in points.windows(2) {
+            length += (w[1] - w[0]).norm();
+            lengths.push(length);
+        }
+
+        TextPath { points, lengths }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.lengths.last().copied().unwrap_or(0.0)
+    }
+
+    /// The point at arc length `s`, and the direction of the path there. Beyond the ends, the
+    /// path goes on in a straight line.
+    pub fn at(&self, s: f32) -> (Vec2, Vec2) {
+        if self.points.len() < 2 {
+            let p = self.points.first().copied().unwrap_or_else(glm::zero);
+            return (p, glm::vec2(1.0, 0.0));
+        }
+
+        // Skip zero length segments, they have no direction.
+        let i = self
+            .lengths
+            .partition_point(|&l| l <= s)
+            .clamp(1, self.points.len() - 1);
+        let (mut a, mut b) = (i - 1, i);
+        while self.lengths[b] - self.lengths[a] < 1e-9 {
+            if b + 1 < self.points.len() {
+                b += 1;
+            } else if a > 0 {
+                a -= 1;
+            } else {
+                // All points are the same.
+                return (self.points[0], glm::vec2(1.0, 0.0));
+            }
+        }
+
+        let (p, q) = (self.points[a], self.points[b]);
+        let dir = (q - p).normalize();
+        (p + (s - self.lengths[a]) * dir, dir)
+    }
+}
+
+impl From<&Cubic> for TextPath {
+    fn from(cubic: &Cubic) -> Self {
+        let points = (0..=SEGMENTS)
+            .map(|i| cubic.r(i as f32 / SEGMENTS as f32))
+            .collect();
+        TextPath::new(points)
+    }
+}
+
+impl From<&LinearSpline> for TextPath {
+    fn from(spline: &LinearSpline) -> Self {
+        let mut points: Vec<Vec2> = Vec::new();
+        for segment in spline.segments() {
+            if points.last() != Some(&segment.p1) {
+                points.push(segment.p1);
+            }
+            points.push(segment.p2);
+        }
+        TextPath::new(points)
+    }
+}
+
+impl From<&Spline> for TextPath {
+    /// Follows the Bézier curves of the spline one after another.
+    fn from(spline: &Spline) -> Self {
+        // Quadratics are a lot flatter than a whole cubic.
+        let n = SEGMENTS / 4;
+        let mut points: Vec<Vec2> = Vec::new();
+        for bez in spline.strokes() {
+            let first = if points.is_empty() { 0 } else { 1 };
+            points.extend((first..=n).map(|i| {
+                let p = bez.at(i as f32 / n as f32);
+                glm::vec2(p.x, p.y)
+            }));
+        }
+        TextPath::new(points)
+    }
+}
+
+impl Default for OnPath {
+    fn default() -> Self {
+        OnPath {
+            align: Align::Left,
+            offset: 0.0,
+            options: ShapingOptions::default(),
+        }
+    }
+}
+
+impl OnPath {
+    pub fn new() -> Self {
+        OnPath::default()
+    }
+
+    /// Where the text goes along the path. `Justify` spreads the glyphs over the whole path.
+    pub fn align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    /// Distance of the baseline from the path, to the left of the direction of the path.
+    /// Use this to put labels above (or, negative, below) a curve.
+    pub fn offset(mut self, offset: f32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn options(mut self, options: ShapingOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Outline the text along the path. The outline is empty if the path has no length.
+    pub fn layout(&self, text: &str, atlas: &Atlas, path: &TextPath) -> Outline {
+        if path.length() <= 0.0 {
+            return Outline {
+                ctrl_pts: Vec::new(),
+                bbox: Rect {
+                    x0: 0.0,
+                    x1: 0.0,
+                    y0: 0.0,
+                    y1: 0.0,
+                },
+                glyphs: Vec::new(),
+            };
+        }
+
+        let mut outline = atlas.outline_with(text, &self.options);
+
+        let width: f32 = outline.glyphs.iter().map(|g| g.advance).sum();
+        let slack = path.length() - width;
+        let (start, spacing) = match self.align {
+            Align::Left => (0.0, 0.0),
+            Align::Right => (slack, 0.0),
+            Align::Center => (0.5 * slack, 0.0),
+            Align::Justify if outline.glyphs.len() > 1 => (
+                0.0,
+                f32::max(slack, 0.0) / (outline.glyphs.len() - 1) as f32,
+            ),
+            Align::Justify => (0.5 * slack, 0.0),
+        };
+
+        let mut bbox: Option<Rect> = None;
+        for (i, g) in outline.glyphs.iter_mut().enumerate() {
+            // The glyph rotates about the centre of its advance, on the baseline.
+            let centre = g.pen.0 + 0.5 * g.advance;
+            let (origin, dir) = path.at(start + i as f32 * spacing + centre);
+            let normal = glm::vec2(-dir.y, dir.x);
+
+            let place = |(x, y): (f32, f32)| {
+                let p = origin + (x - centre) * dir + (y + self.offset) * normal;
+                (p.x, p.y)
+            };
+
+            let mut glyph_box: Option<Rect> = None;
+            for p in &mut outline.ctrl_pts[g.ctrl.clone()] {
+                *p = place(*p);
+                let r = Rect {
+                    x0: p.0,
+                    x1: p.0,
+                    y0: p.1,
+                    y1: p.1,
+                };
+                glyph_box = Some(glyph_box.map_or(r, |b| b.extend(r)));
+            }
+
+            g.pen = place(g.pen);
+            match glyph_box {
+                Some(glyph_box) => {
+                    g.bbox = glyph_box;
+                    bbox = Some(bbox.map_or(glyph_box, |b| b.extend(glyph_box)));
+                }
+                // Spaces have no ink.
+                None => {
+                    g.bbox = Rect {
+                        x0: g.pen.0,
+                        x1: g.pen.0,
+                        y0: g.pen.1,
+                        y1: g.pen.1,
+                    }
+                }
+            }
+        }
+
+        if let Some(bbox) = bbox {
+            outline.bbox = bbox;
+        }
+        outline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::LM_ROMAN;
+    use crate::line::Segment;
+
+    #[test]
+    fn degenerate_paths_have_no_length() {
+        let p = glm::vec2(1.0, 2.0);
+        let paths = [
+            TextPath::new(Vec::new()),
+            TextPath::new(vec![p]),
+            TextPath::new(vec![p, p, p]),
+            TextPath::from(&Spline::builder().build()),
+            TextPath::from(&Segment::spline(&[p])),
+        ];
+
+        let atlas = Atlas::new(&LM_ROMAN);
+        for path in &paths {
+            assert_eq!(path.length(), 0.0);
+            let (q, dir) = path.at(1.0);
+            assert!(q.x.is_finite() && q.y.is_finite() && dir.norm() > 0.0);
+
+            let outline = OnPath::new().layout("label", &atlas, path);
+            assert!(outline.glyphs.is_empty() && outline.ctrl_pts.is_empty());
+        }
+    }
+
+    #[test]
+    fn text_follows_a_straight_path() {
+        let path = TextPath::new(vec![glm::vec2(0.0, 0.0), glm::vec2(0.0, 10.0)]);
+        assert_eq!(path.length(), 10.0);
+
+        // Going up, the baseline is the y-axis, and the glyphs lie to the left of it.
+        let outline = OnPath::new().layout("label", &Atlas::new(&LM_ROMAN), &path);
+        assert_eq!(outline.glyphs.len(), 5);
+        // (Apart from the overshoot of the round letters)
+        assert!(outline.bbox.x1 < 0.02 && outline.bbox.y0 >= 0.0);
+        assert!(outline.bbox.x0 < -0.5 && outline.bbox.y1 > 1.5);
+    }
+}
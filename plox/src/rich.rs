@@ -0,0 +1,200 @@
+//! Rich text.
+//!
+//! A label like "velocity **v** in m/s" mixes faces, sizes and colours. Each span of it is
+//! shaped with its own atlas, and the spans are put one after another using their real
+//! advances, on a common baseline. The result is a stack of colour layers, which a
+//! `ColorTextElement` draws in one go:
+//!
+//! ```no_run
+//! use plox::atlas::Atlas;
+//! use plox::font::LM_ROMAN;
+//! use plox::gpu::text::ColorTextElement;
+//! use plox::rich::{RichText, Span};
+//! use plox::style::Synthesis;
+//!
+//! let roman = Atlas::new(&LM_ROMAN);
+//! let red = nalgebra_glm::vec4(0.8, 0.1, 0.1, 1.0);
+//! let label = RichText::new()
+//!     .span(Span::new("velocity ", &roman))
+//!     .span(Span::new("v", &roman).style(Synthesis::new().bold()).color(red))
+//!     .span(Span::new(" in m/s", &roman).size(0.8));
+//! // (This needs a current OpenGL context)
+//! let element = unsafe { ColorTextElement::layered(label.layout()) };
+//! ```
+//!
+//! Sizes and shifts are relative to the size of the text element, like everything in em.
+use crate::atlas::{Atlas, ColorLayer, Outline};
+use crate::shaping::ShapingOptions;
+use crate::spline::Rect;
+use crate::style::Synthesis;
+
+/// A run of text in a single style.
+pub struct Span<'x, 'a> {
+    text: String,
+    atlas: &'x Atlas<'a>,
+    size: f32,
+    color: Option<glm::Vec4>,
+    shift: f32,
+    style: Synthesis,
+    options: ShapingOptions,
+}
+
+/// Text made of differently styled spans. (See the module docs)
+#[derive(Default)]
+pub struct RichText<'x, 'a> {
+    spans: Vec<Span<'x, 'a>>,
+}
+
+impl<'x, 'a> Span<'x, 'a> {
+    pub fn new(text: impl Into<String>, atlas: &'x Atlas<'a>) -> Self {
+        Span {
+            text: text.into(),
+            atlas,
+            size: 1.0,
+            color: None,
+            shift: 0.0,
+            style: Synthesis::new(),
+            options: ShapingOptions::default(),
+        }
+    }
+
+    /// Size relative to the rest of the text.
+    pub fn size(mut self, size: f32) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Colour of the span. Spans without a colour have the colour of the text.
+    pub fn color(mut self, color: glm::Vec4) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    /// Move the span up by `shift` (or down, if negative), for super- and subscripts.
+    pub fn shift(mut self, shift: f32) -> Self {
+        self.shift = shift;
+        self
+    }
+
+    pub fn style(mut self, style: Synthesis) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub fn options(mut self, options: ShapingOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl<'x, 'a> RichText<'x, 'a> {
+    pub fn new() -> Self {
+        RichText { spans: Vec::new() }
+    }
+
+    pub fn span(mut self, span: Span<'x, 'a>) -> Self {
+        self.spans.push(span);
+        self
+    }
+
+    /// All the text, without styles. The clusters of the laid out glyphs refer to this.
+    pub fn text(&self) -> String {
+        self.spans.iter().map(|s| s.text.as_str()).collect()
+    }
+
+    /// Shape and place the spans. Consecutive spans of the same colour share a layer, unless
+    /// their glyphs overlap. Overlapping parts of a layer would cancel out when it is filled.
+    pub fn layout(&self) -> Vec<ColorLayer> {
+        let mut layers: Vec<ColorLayer> = Vec::new();
+        let mut pen = 0.0;
+        let mut start = 0;
+
+        for span in &self.spans {
+            let mut outline = span
+                .atlas
+                .outline_synthetic(&span.text, &span.options, &span.style);
+            let advance: f32 = outline.glyphs.iter().map(|g| g.advance).sum();
+
+            place(&mut outline, span.size, pen, span.shift, start);
+            pen += span.size * advance;
+            start += span.text.len();
+
+            match layers.last_mut() {
+                Some(layer) if layer.color == span.color && !overlap(&layer.outline, &outline) => {
+                    append(&mut layer.outline, outline)
+                }
+                _ => layers.push(ColorLayer {
+                    outline,
+                    color: span.color,
+                }),
+            }
+        }
+
+        layers
+    }
+}
+
+/// Scale an outline, move it to `(x, y)`, and its clusters by `offset` bytes.
+fn place(outline: &mut Outline, scale: f32, x: f32, y: f32, offset: usize) {
+    let rect = |r: Rect| Rect {
+        x0: scale * r.x0 + x,
+        x1: scale * r.x1 + x,
+        y0: scale * r.y0 + y,
+        y1: scale * r.y1 + y,
+    };
+
+    for p in &mut outline.ctrl_pts {
+        *p = (scale * p.0 + x, scale * p.1 + y);
+    }
+    outline.bbox = rect(outline.bbox);
+
+    for g in &mut outline.glyphs {
+        g.pen = (scale * g.pen.0 + x, scale * g.pen.1 + y);
+        g.advance *= scale;
+        g.bbox = rect(g.bbox);
+        g.cluster = g.cluster.start + offset..g.cluster.end + offset;
+    }
+}
+
+/// Whether any glyphs of the outlines overlap.
+fn overlap(a: &Outline, b: &Outline) -> bool {
+    a.bbox.intersects(&b.bbox)
+        && a.glyphs
+            .iter()
+            .any(|g| b.glyphs.iter().any(|h| g.bbox.intersects(&h.bbox)))
+}
+
+/// Put the glyphs of `other` after those of `outline`.
+fn append(outline: &mut Outline, other: Outline) {
+    let n = outline.ctrl_pts.len();
+    outline.ctrl_pts.extend(other.ctrl_pts);
+    outline.bbox = outline.bbox.extend(other.bbox);
+    outline.glyphs.extend(other.glyphs.into_iter().map(|mut g| {
+        g.ctrl = g.ctrl.start + n..g.ctrl.end + n;
+        g
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::LM_ROMAN_ITALIC;
+
+    #[test]
+    fn overlapping_spans_get_their_own_layer() {
+        let atlas = Atlas::new(&LM_ROMAN_ITALIC);
+        let layout = |a, b| {
+            RichText::new()
+                .span(Span::new(a, &atlas))
+                .span(Span::new(b, &atlas))
+                .layout()
+        };
+
+        // The hook of the f reaches over the j.
+        let layers = layout("f", "j");
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[1].outline.glyphs[0].cluster, 1..2);
+
+        assert_eq!(layout("a", "b").len(), 1);
+    }
+}
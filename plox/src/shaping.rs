@@ -1,6 +1,20 @@
 //! Text shaping!
+//!
+//! Strings that mix left-to-right and right-to-left scripts (Hebrew or Arabic labels with
+//! Latin numbers, say) are split into directional runs with the Unicode bidi algorithm. Each
+//! run is shaped on its own, and the runs are put in visual order.
+//!
+//! Vertical text (`Direction::TopToBottom`) goes down from the origin, with the glyphs centred
+//! on the line x = 0. Faces with vertical metrics set their glyphs upright, as in East Asian
+//! text. Other faces are shaped horizontally, and the glyphs are turned 90° clockwise.
+//! `Direction::BottomToTop` is the same, but with the order of the clusters reversed, so the
+//! first character ends up at the bottom.
 use crate::spline::Rect;
 use rustybuzz::{self as buzz, Face, GlyphInfo, GlyphPosition, UnicodeBuffer};
+use std::cell::RefCell;
+use std::hash::{Hash, Hasher};
+use unicode_bidi::BidiInfo;
+pub use rustybuzz::{Direction, Feature, Language, Script, Tag};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Glyph {
@@ -9,30 +23,287 @@ pub struct Glyph {
     pub bbox: Rect,
     pub x: f32,
     pub y: f32,
+    /// How far to move the pen after drawing the glyph, in units of 1em, along the line: To
+    /// the right in horizontal text, and down in vertical text. (Glyphs are in visual order,
+    /// so this is also true for right-to-left and bottom-to-top text.) For upright glyphs
+    /// in vertical text, this is the vertical advance of the face, and for turned glyphs,
+    /// their horizontal advance.
+    pub advance: f32,
+    /// Byte offset of the start of the cluster in the input the glyph belongs to.
+    pub cluster: usize,
+    /// The glyph is turned 90° clockwise, for vertical text in a face without vertical
+    /// metrics. The bounding box is already turned, the outline has to be turned when it is
+    /// drawn.
+    pub rotated: bool,
+    /// The glyph is part of vertical text, upright or turned.
+    pub vertical: bool,
+}
+
+/// The glyph id faces use for characters they do not have a glyph for.
+pub const NOTDEF: usize = 0;
+
+/// Everything that controls how a string is shaped, apart from the face itself.
+/// Anything that is not set explicitly is guessed from the text.
+#[derive(Debug, Clone, Default)]
+pub struct ShapingOptions {
+    pub features: Vec<Feature>,
+    pub script: Option<Script>,
+    pub language: Option<Language>,
+    pub direction: Option<Direction>,
+}
+
+impl ShapingOptions {
+    pub fn new() -> Self {
+        ShapingOptions::default()
+    }
+
+    /// Set an OpenType feature for the whole string.
+    /// A value of 0 turns it off, and for features with alternatives, the value picks one.
+    pub fn feature(mut self, tag: &[u8; 4], value: u32) -> Self {
+        self.features.push(Feature::new(Tag::from_bytes(tag), value, ..));
+        self
+    }
+
+    pub fn script(mut self, script: Script) -> Self {
+        self.script = Some(script);
+        self
+    }
+
+    /// Set the language from a BCP 47 tag, for example "en" or "nb-NO".
+    /// An empty tag leaves the language unset.
+    pub fn language(mut self, language: &str) -> Self {
+        self.language = language.parse().ok();
+        self
+    }
+
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    /// Whether the text goes top to bottom, or bottom to top.
+    pub fn is_vertical(&self) -> bool {
+        matches!(
+            self.direction,
+            Some(Direction::TopToBottom | Direction::BottomToTop)
+        )
+    }
+
+    /// Numerals of equal width, so tick labels line up.
+    pub fn tabular_numerals(self) -> Self {
+        self.feature(b"tnum", 1)
+    }
+
+    /// Old-style numerals that blend in with lowercase text.
+    pub fn oldstyle_numerals(self) -> Self {
+        self.feature(b"onum", 1)
+    }
+
+    pub fn small_caps(self) -> Self {
+        self.feature(b"smcp", 1)
+    }
+
+    /// Turn standard and contextual ligatures on or off.
+    pub fn ligatures(self, on: bool) -> Self {
+        let value = on as u32;
+        self.feature(b"liga", value).feature(b"clig", value)
+    }
+
+    /// Math script style: 1 for (sub/super)scripts, 2 for second level scripts.
+    pub fn math_script(self, level: u32) -> Self {
+        self.feature(b"ssty", level)
+    }
+
+    /// Stylistic set 1 through 20.
+    pub fn stylistic_set(self, n: u8) -> Self {
+        assert!((1..=20).contains(&n), "stylistic sets are numbered 1 to 20");
+        let tag = [b's', b's', b'0' + n / 10, b'0' + n % 10];
+        self.feature(&tag, 1)
+    }
+}
+
+// The options are used as part of cache keys. (See `cache::TextCache`)
+// Not all of the rustybuzz types implement `Hash`, so we hash their tags.
+impl Hash for ShapingOptions {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for f in &self.features {
+            (f.tag, f.value, f.start, f.end).hash(state);
+        }
+        self.script.map(|s| s.tag()).hash(state);
+        self.language.hash(state);
+        self.direction.hash(state);
+    }
+}
+
+impl PartialEq for ShapingOptions {
+    fn eq(&self, other: &Self) -> bool {
+        self.features == other.features
+            && self.script == other.script
+            && self.language == other.language
+            && self.direction == other.direction
+    }
+}
+
+impl Eq for ShapingOptions {}
+
+thread_local! {
+    // The unicode buffer is re-used between calls, to save allocating one for every string.
+    static BUFFER: RefCell<Option<UnicodeBuffer>> = const { RefCell::new(None) };
 }
 
 pub fn shape<S>(text: S, face: &Face) -> Vec<Glyph>
 where
     S: AsRef<str>,
 {
-    // TODO: We can save this allocation if we re-use the buffer.
-    let mut unicode_buffer = UnicodeBuffer::new();
-    unicode_buffer.push_str(text.as_ref());
+    shape_with(text, face, &ShapingOptions::default())
+}
+
+/// Shape a string. Unless the options force a direction, the string is run through the bidi
+/// algorithm first, and the glyphs come out in visual order (left to right).
+pub fn shape_with<S>(text: S, face: &Face, options: &ShapingOptions) -> Vec<Glyph>
+where
+    S: AsRef<str>,
+{
+    let text = text.as_ref();
+
+    if options.is_vertical() && !has_vertical_metrics(face) {
+        return rotate(text, face, options);
+    }
+
+    // Skip the analysis in the common case.
+    if options.direction.is_some() || text.is_ascii() {
+        return shape_run(text, face, options);
+    }
+
+    let bidi = BidiInfo::new(text, None);
+    if !bidi.has_rtl() {
+        return shape_run(text, face, options);
+    }
+
+    let mut glyphs = Vec::with_capacity(text.len());
+    let mut pen = 0.0;
+
+    for paragraph in &bidi.paragraphs {
+        let (levels, runs) = bidi.visual_runs(paragraph, paragraph.range.clone());
+
+        for run in runs {
+            let direction = if levels[run.start].is_rtl() {
+                Direction::RightToLeft
+            } else {
+                Direction::LeftToRight
+            };
+            let options = options.clone().direction(direction);
 
-    let glyph_buffer = buzz::shape(face, &[], unicode_buffer);
+            let start = glyphs.len();
+            glyphs.extend(shape_run(&text[run.clone()], face, &options));
+
+            // Move the run after the previous ones, and make the clusters refer to the
+            // whole string.
+            let mut advance = 0.0;
+            for g in &mut glyphs[start..] {
+                g.x += pen;
+                g.cluster += run.start;
+                advance += g.advance;
+            }
+            pen += advance;
+        }
+    }
+
+    glyphs
+}
+
+/// Whether the face has vertical metrics, so it can be set upright in vertical text.
+pub fn has_vertical_metrics(face: &Face) -> bool {
+    face.tables().vmtx.is_some()
+}
+
+/// Vertical text in a face without vertical metrics: Shape it horizontally, and turn it.
+fn rotate(text: &str, face: &Face, options: &ShapingOptions) -> Vec<Glyph> {
+    let backward = options.direction == Some(Direction::BottomToTop);
+    let options = ShapingOptions {
+        direction: None,
+        ..options.clone()
+    };
+
+    // Centre the glyphs on the line, like upright glyphs are.
+    let em = face.units_per_em() as f32;
+    let centre = (face.ascender() as f32 + face.descender() as f32) / (2.0 * em);
+
+    let mut glyphs = shape_with(text, face, &options);
+    if backward {
+        glyphs = reverse(&glyphs);
+    }
+
+    for g in &mut glyphs {
+        let Rect { x0, x1, y0, y1 } = g.bbox;
+        g.bbox = Rect {
+            x0: y0,
+            x1: y1,
+            y0: -x1,
+            y1: -x0,
+        };
+        (g.x, g.y) = (g.y - centre, -g.x);
+        g.rotated = true;
+        g.vertical = true;
+    }
+    glyphs
+}
+
+/// Reverse the order of the clusters of a horizontal line. The glyphs of a cluster keep their
+/// order and their positions relative to each other, so marks stay on their base.
+fn reverse(glyphs: &[Glyph]) -> Vec<Glyph> {
+    let width: f32 = glyphs.iter().map(|g| g.advance).sum();
+
+    glyphs
+        .chunk_by(|a, b| a.cluster == b.cluster)
+        .rev()
+        .flat_map(|cluster| {
+            let start = cluster[0].x;
+            let advance: f32 = cluster.iter().map(|g| g.advance).sum();
+            let moved = width - start - advance - start;
+            cluster.iter().map(move |g| Glyph { x: g.x + moved, ..*g })
+        })
+        .collect()
+}
+
+/// Shape a run of text that goes in one direction.
+fn shape_run(text: &str, face: &Face, options: &ShapingOptions) -> Vec<Glyph> {
+    let mut unicode_buffer = BUFFER
+        .with(|buffer| buffer.borrow_mut().take())
+        .unwrap_or_default();
+    unicode_buffer.push_str(text);
+
+    if let Some(script) = options.script {
+        unicode_buffer.set_script(script);
+    }
+
+    if let Some(language) = &options.language {
+        unicode_buffer.set_language(language.clone());
+    }
+
+    if let Some(direction) = options.direction {
+        unicode_buffer.set_direction(direction);
+    }
+
+    let glyph_buffer = buzz::shape(face, &options.features, unicode_buffer);
+    let vertical = options.is_vertical();
 
     let mut x = 0.0;
     let mut y = 0.0;
-    let mut glyphs = vec![];
+    let mut glyphs = Vec::with_capacity(glyph_buffer.len());
     let em = face.units_per_em() as f32;
 
     for i in 0..glyph_buffer.len() {
-        let GlyphInfo { glyph_id, .. } = glyph_buffer.glyph_infos()[i];
+        let GlyphInfo {
+            glyph_id, cluster, ..
+        } = glyph_buffer.glyph_infos()[i];
 
         // Advances: How much to advance *after* drawing the glyph
         let GlyphPosition {
             x_advance,
             y_advance,
+            x_offset,
+            y_offset,
             ..
         } = glyph_buffer.glyph_positions()[i];
 
@@ -40,6 +311,14 @@ where
         let x_advance = x_advance as f32 / em;
         let y_advance = y_advance as f32 / em;
 
+        // In vertical text, the offsets move the glyph from the pen to where its (horizontal)
+        // origin goes, and the pen moves down.
+        let (dx, dy, advance) = if vertical {
+            (x_offset as f32 / em, y_offset as f32 / em, -y_advance)
+        } else {
+            (0.0, 0.0, x_advance)
+        };
+
         let bbox = face.glyph_bounding_box(ttf::GlyphId(glyph_id as u16));
 
         // Not all glyphs actually have bounding boxes.
@@ -54,8 +333,12 @@ where
             glyphs.push(Glyph {
                 glyph_id: glyph_id as usize,
                 bbox,
-                x,
-                y,
+                x: x + dx,
+                y: y + dy,
+                advance,
+                cluster: cluster as usize,
+                rotated: false,
+                vertical,
             });
         } else {
             let bbox = Rect {
@@ -68,8 +351,12 @@ where
             glyphs.push(Glyph {
                 glyph_id: glyph_id as usize,
                 bbox,
-                x,
-                y,
+                x: x + dx,
+                y: y + dy,
+                advance,
+                cluster: cluster as usize,
+                rotated: false,
+                vertical,
             });
         }
 
@@ -78,5 +365,86 @@ where
         y += y_advance;
     }
 
+    // Hand the buffer back for the next call. Clearing resets everything, including the
+    // script, language and direction.
+    BUFFER.with(|buffer| buffer.replace(Some(glyph_buffer.clear())));
+
     glyphs
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::LM_ROMAN;
+
+    fn vertical(text: &str, direction: Direction) -> Vec<Glyph> {
+        let glyphs = shape_with(text, &LM_ROMAN, &ShapingOptions::new().direction(direction));
+        assert!(glyphs.iter().all(|g| g.vertical && g.rotated));
+        glyphs
+    }
+
+    #[test]
+    fn bottom_to_top_reverses_the_clusters() {
+        let down = vertical("AVq\u{301}", Direction::TopToBottom);
+        let up = vertical("AVq\u{301}", Direction::BottomToTop);
+        let clusters = |glyphs: &[Glyph]| glyphs.iter().map(|g| g.cluster).collect::<Vec<_>>();
+        assert_eq!(clusters(&down), [0, 1, 2, 2]);
+        assert_eq!(clusters(&up), [2, 2, 1, 0]);
+
+        // Both start at the origin, go down, and end at the same height.
+        let end = |glyphs: &[Glyph]| glyphs.iter().map(|g| g.advance).sum::<f32>();
+        assert_eq!(down[0].y, 0.0);
+        assert_eq!(up[0].y, 0.0);
+        assert!((end(&down) - end(&up)).abs() < 1e-5);
+
+        // The mark stays on the q, and the A is at the bottom.
+        assert!((up[1].y - up[0].y - (down[3].y - down[2].y)).abs() < 1e-6);
+        assert!((up[3].y - up[3].advance + end(&up)).abs() < 1e-5);
+        assert!(up.iter().all(|g| g.x == down[0].x));
+    }
+
+    fn clusters(glyphs: &[Glyph]) -> Vec<usize> {
+        glyphs.iter().map(|g| g.cluster).collect()
+    }
+
+    /// Each glyph starts where the one before it ends.
+    fn assert_continuous(glyphs: &[Glyph]) {
+        for w in glyphs.windows(2) {
+            assert!((w[1].x - w[0].x - w[0].advance).abs() < 1e-5);
+        }
+    }
+
+    // (LM Roman has no Hebrew, but .notdef has an advance, and clusters all the same)
+
+    #[test]
+    fn right_to_left_text_is_reversed() {
+        let glyphs = shape("אבג", &LM_ROMAN);
+        assert_eq!(clusters(&glyphs), [4, 2, 0]);
+        assert_eq!(glyphs[0].x, 0.0);
+        assert_continuous(&glyphs);
+    }
+
+    #[test]
+    fn runs_are_put_in_visual_order() {
+        // A left to right paragraph, with a right to left run in the middle.
+        let glyphs = shape("ab אבג cd", &LM_ROMAN);
+        assert_eq!(clusters(&glyphs), [0, 1, 2, 7, 5, 3, 9, 10, 11]);
+        assert_continuous(&glyphs);
+
+        // A right to left paragraph starts at the right, so the Latin comes first.
+        let glyphs = shape("אבג ab", &LM_ROMAN);
+        assert_eq!(clusters(&glyphs), [7, 8, 6, 4, 2, 0]);
+        assert_continuous(&glyphs);
+
+        // The clusters are those of the whole string.
+        let text = "ab אבג cd";
+        let first = shape("ab ", &LM_ROMAN);
+        let glyphs = shape(text, &LM_ROMAN);
+        assert!(text[glyphs[3].cluster..].starts_with('ג'));
+        assert!(text[glyphs[5].cluster..].starts_with('א'));
+
+        // And the right to left run starts where the first one ends.
+        let end: f32 = first.iter().map(|g| g.advance).sum();
+        assert!((glyphs[3].x - end).abs() < 1e-5);
+    }
+}
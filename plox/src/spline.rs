@@ -67,6 +67,11 @@ impl Quadratic {
 }
 
 impl Spline {
+    /// Assemble a spline from curves and their bounding box.
+    pub fn from_parts(beziers: Vec<Quadratic>, bbox: Rect) -> Spline {
+        Spline { beziers, bbox }
+    }
+
     /// Iterator over the strokes of the spline. I. e. references
     /// to the underlying quadratic Bézier curves.
     pub fn strokes(&self) -> impl Iterator<Item = &Quadratic> {
@@ -365,6 +370,11 @@ impl Rect {
         let y1 = f32::max(self.y1, rect.y1);
         Rect { x0, y0, x1, y1 }
     }
+
+    /// Whether the rectangles overlap. Rectangles that only touch don't.
+    pub fn intersects(&self, rect: &Rect) -> bool {
+        self.x0 < rect.x1 && rect.x0 < self.x1 && self.y0 < rect.y1 && rect.y0 < self.y1
+    }
 }
 
 impl<const N: usize> std::fmt::Display for Poly<N> {
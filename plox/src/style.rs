@@ -0,0 +1,347 @@
+//! Synthetic styles.
+//!
+//! Not every face comes with bold and italic variants, and math fonts never do. These styles
+//! are faked by transforming the glyph outlines before they are laid out:
+//!
+//! - Emboldening offsets the contours outwards, along their normals.
+//! - Oblique shears the glyphs to the right.
+//! - Outlined text keeps only a band around the contours. This falls out of the XOR fill: A
+//!   glyph offset outwards and the same glyph offset inwards, drawn together, cancel out
+//!   everywhere but between the two.
+//!
+//! The offsets are approximate (the offset of a Bézier curve is not a Bézier curve), but for the
+//! small offsets used for text the error is invisible.
+use crate::spline::{Point, Quadratic, Rect, Spline};
+use glm::Vec2;
+
+/// Joins where the miter would be longer than this many offsets are bevelled instead.
+const MITER_LIMIT: f32 = 4.0;
+
+/// A synthetic style. All lengths are in units of 1em.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Synthesis {
+    pub(crate) embolden: f32,
+    pub(crate) slant: f32,
+    pub(crate) stroke: Option<f32>,
+}
+
+impl Synthesis {
+    /// No synthesis at all.
+    pub fn new() -> Self {
+        Synthesis::default()
+    }
+
+    /// Offset the contours by `amount` outwards. Negative amounts make the text thinner.
+    /// The glyphs are spaced out (or in) to make room.
+    pub fn embolden(mut self, amount: f32) -> Self {
+        self.embolden = amount;
+        self
+    }
+
+    /// Emboldening that roughly matches the bold Latin Modern faces.
+    pub fn bold(self) -> Self {
+        self.embolden(0.02)
+    }
+
+    /// Shear the glyphs by moving points `slant` to the right per unit of height.
+    pub fn slant(mut self, slant: f32) -> Self {
+        self.slant = slant;
+        self
+    }
+
+    /// A slant of about 12°, which is what most italic faces use.
+    pub fn oblique(self) -> Self {
+        self.slant(0.21)
+    }
+
+    /// Only draw a band of the given width around the contours.
+    pub fn outlined(mut self, width: f32) -> Self {
+        self.stroke = Some(width);
+        self
+    }
+
+    /// How much wider each glyph gets.
+    pub fn extra_advance(&self) -> f32 {
+        2.0 * self.embolden
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Synthesis::default()
+    }
+
+    /// Apply the style to the outline of a single glyph, in the coordinate system of the glyph.
+    pub fn apply(&self, spline: &Spline) -> Spline {
+        if spline.len() == 0 {
+            return Spline::from_parts(Vec::new(), *spline.bbox());
+        }
+
+        let contours = contours(spline);
+        // Move the glyph right, so it keeps its side bearing.
+        let shift = glm::vec2(self.embolden, 0.0);
+
+        let mut beziers: Vec<[Vec2; 3]> = match self.stroke {
+            None => offset(&contours, self.embolden),
+            Some(width) => {
+                let mut band = offset(&contours, self.embolden + 0.5 * width);
+                band.extend(offset(&contours, self.embolden - 0.5 * width));
+                band
+            }
+        }
+        .into_iter()
+        .flatten()
+        .collect();
+
+        for bez in &mut beziers {
+            for p in bez.iter_mut() {
+                *p += shift;
+                p.x += self.slant * p.y;
+            }
+        }
+
+        let mut bbox = Rect {
+            x0: f32::INFINITY,
+            x1: -f32::INFINITY,
+            y0: f32::INFINITY,
+            y1: -f32::INFINITY,
+        };
+        for p in beziers.iter().flatten() {
+            bbox = bbox.extend(Rect {
+                x0: p.x,
+                x1: p.x,
+                y0: p.y,
+                y1: p.y,
+            });
+        }
+
+        let point = |p: Vec2| Point { x: p.x, y: p.y };
+        let beziers = beziers
+            .into_iter()
+            .map(|[p0, p1, p2]| Quadratic(point(p0), point(p1), point(p2)))
+            .collect();
+
+        Spline::from_parts(beziers, bbox)
+    }
+}
+
+/// Split a spline into its closed contours.
+fn contours(spline: &Spline) -> Vec<Vec<[Vec2; 3]>> {
+    let v = |p: Point| glm::vec2(p.x, p.y);
+    let mut contours: Vec<Vec<[Vec2; 3]>> = Vec::new();
+
+    for bez in spline.strokes() {
+        let bez = [v(bez.0), v(bez.1), v(bez.2)];
+        match contours.last_mut() {
+            // Continues the current contour.
+            Some(contour) if (contour[contour.len() - 1][2] - bez[0]).norm() < 1e-5 => {
+                contour.push(bez)
+            }
+            _ => contours.push(vec![bez]),
+        }
+    }
+
+    contours
+}
+
+/// Offset all contours by `d` towards the outside of the filled area.
+fn offset(contours: &[Vec<[Vec2; 3]>], d: f32) -> Vec<Vec<[Vec2; 3]>> {
+    // The outer contour of a glyph has the largest area, and all contours are oriented the
+    // same way relative to the fill (TrueType and CFF just disagree on which way that is).
+    // So the orientation of the outer contour tells which side of the curves is outside.
+    let areas: Vec<f32> = contours.iter().map(|c| area(c)).collect();
+    let outer = areas
+        .iter()
+        .copied()
+        .fold(0.0, |a: f32, b: f32| if b.abs() > a.abs() { b } else { a });
+    let d = if outer > 0.0 { d } else { -d };
+
+    contours.iter().map(|c| offset_contour(c, d)).collect()
+}
+
+/// Signed area of a contour, positive if it goes counter-clockwise. The control polygon is
+/// close enough.
+fn area(contour: &[[Vec2; 3]]) -> f32 {
+    let cross = |a: Vec2, b: Vec2| a.x * b.y - a.y * b.x;
+    0.5 * contour
+        .iter()
+        .map(|[p0, p1, p2]| cross(*p0, *p1) + cross(*p1, *p2))
+        .sum::<f32>()
+}
+
+/// Offset a closed contour by `d` to the right of its direction of travel.
+fn offset_contour(contour: &[[Vec2; 3]], d: f32) -> Vec<[Vec2; 3]> {
+    // Normal to the right of a direction.
+    let normal = |v: Vec2| {
+        let n = glm::vec2(v.y, -v.x);
+        if n.norm() > 1e-9 {
+            n.normalize()
+        } else {
+            n
+        }
+    };
+
+    // Tangents at the ends of a curve. If the control point sits on an end point, the
+    // tangent there points to the other end.
+    let start = |[p0, p1, p2]: [Vec2; 3]| {
+        if (p1 - p0).norm() > 1e-6 {
+            p1 - p0
+        } else {
+            p2 - p0
+        }
+    };
+    let end = |[p0, p1, p2]: [Vec2; 3]| {
+        if (p2 - p1).norm() > 1e-6 {
+            p2 - p1
+        } else {
+            p2 - p0
+        }
+    };
+
+    let n = contour.len();
+    let mut out = Vec::with_capacity(n);
+
+    for i in 0..n {
+        let prev = contour[(i + n - 1) % n];
+        let bez = contour[i];
+        let next = contour[(i + 1) % n];
+
+        // The end points sit on the miters of the joins with the neighbours, so the offset
+        // contour stays closed.
+        let p0 = bez[0] + d * miter(normal(end(prev)), normal(start(bez)));
+        let p2 = bez[2] + d * miter(normal(end(bez)), normal(start(next)));
+        let p1 = bez[1] + d * miter(normal(start(bez)), normal(end(bez)));
+
+        out.push([p0, p1, p2]);
+    }
+
+    out
+}
+
+/// The offset direction at a join between two normals, scaled so both edges end up at the
+/// offset distance.
+fn miter(a: Vec2, b: Vec2) -> Vec2 {
+    let m = a + b;
+    let cos = 0.5 * m.norm();
+    if cos * MITER_LIMIT < 1.0 {
+        // Too sharp. Bevel instead, which just keeps the corner where it is at worst.
+        return m / MITER_LIMIT;
+    }
+    m / (2.0 * cos * cos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atlas::Atlas;
+    use crate::font::LM_ROMAN;
+    use crate::shaping::ShapingOptions;
+
+    /// A unit square, counter-clockwise, made of straight quadratics.
+    fn square() -> Spline {
+        let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let point = |(x, y): (f32, f32)| Point { x, y };
+        let beziers = (0..4)
+            .map(|i| {
+                let (a, b) = (corners[i], corners[(i + 1) % 4]);
+                let mid = (0.5 * (a.0 + b.0), 0.5 * (a.1 + b.1));
+                Quadratic(point(a), point(mid), point(b))
+            })
+            .collect();
+        Spline::from_parts(
+            beziers,
+            Rect {
+                x0: 0.0,
+                x1: 1.0,
+                y0: 0.0,
+                y1: 1.0,
+            },
+        )
+    }
+
+    fn assert_close(a: f32, b: f32, tolerance: f32) {
+        assert!((a - b).abs() <= tolerance, "{a} is not {b}");
+    }
+
+    #[test]
+    fn embolden_grows_the_box() {
+        let a = 0.05;
+        let style = Synthesis::new().embolden(a);
+
+        // The glyph moves right by the amount, so it keeps its left side bearing.
+        let b = *style.apply(&square()).bbox();
+        assert_close(b.x0, 0.0, 1e-5);
+        assert_close(b.x1, 1.0 + 2.0 * a, 1e-5);
+        assert_close(b.y0, -a, 1e-5);
+        assert_close(b.y1, 1.0 + a, 1e-5);
+
+        // Round glyphs only roughly.
+        let atlas = Atlas::new(&LM_ROMAN);
+        let id = LM_ROMAN.glyph_index('o').unwrap().0 as usize;
+        let (o, bold) = (atlas.glyph(id).bbox(), *style.apply(atlas.glyph(id)).bbox());
+        assert_close(bold.x0, o.x0, 0.2 * a);
+        assert_close(bold.x1, o.x1 + 2.0 * a, 0.2 * a);
+        assert_close(bold.y0, o.y0 - a, 0.2 * a);
+        assert_close(bold.y1, o.y1 + a, 0.2 * a);
+
+        // Thinner, for negative amounts.
+        let b = *Synthesis::new().embolden(-a).apply(&square()).bbox();
+        assert_close(b.x1, 1.0 - 2.0 * a, 1e-5);
+        assert_close(b.y1, 1.0 - a, 1e-5);
+    }
+
+    #[test]
+    fn oblique_shears_x() {
+        let square = square();
+        let slanted = Synthesis::new().oblique().apply(&square);
+        for (q, s) in square.strokes().zip(slanted.strokes()) {
+            for (p, s) in [(q.0, s.0), (q.1, s.1), (q.2, s.2)] {
+                assert_close(s.x, p.x + 0.21 * p.y, 1e-6);
+                assert_eq!(s.y, p.y);
+            }
+        }
+        assert_close(slanted.bbox().x1, 1.21, 1e-6);
+    }
+
+    #[test]
+    fn outlined_glyphs_are_a_band() {
+        let w = 0.1;
+        let band = Synthesis::new().outlined(w).apply(&square());
+
+        let contours = contours(&band);
+        assert_eq!(contours.len(), 2);
+        let boxes: Vec<(f32, f32)> = contours
+            .iter()
+            .map(|c| {
+                let xs = c.iter().flatten().map(|p| p.x);
+                let x0 = xs.clone().fold(f32::INFINITY, f32::min);
+                (x0, xs.fold(-f32::INFINITY, f32::max))
+            })
+            .collect();
+
+        // Half the width outside the square, and half inside.
+        assert_close(boxes[0].0, -0.5 * w, 1e-5);
+        assert_close(boxes[0].1, 1.0 + 0.5 * w, 1e-5);
+        assert_close(boxes[1].0, 0.5 * w, 1e-5);
+        assert_close(boxes[1].1, 1.0 - 0.5 * w, 1e-5);
+        // And both go the same way round, so they cancel out inside.
+        assert!(area(&contours[0]) > 0.0 && area(&contours[1]) > 0.0);
+    }
+
+    #[test]
+    fn halos_are_under_the_text() {
+        let atlas = Atlas::new(&LM_ROMAN);
+        let (width, color) = (0.05, glm::vec4(1.0, 1.0, 1.0, 1.0));
+        let options = ShapingOptions::default();
+        let layers = atlas.halo("halo", &options, &Synthesis::new(), width, color);
+
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].color, Some(color));
+        assert_eq!(layers[1].color, None);
+
+        let (halo, text) = (layers[0].outline.bbox, layers[1].outline.bbox);
+        assert_eq!(layers[1].outline.ctrl_pts, atlas.outline("halo").ctrl_pts);
+        assert_close(halo.x0, text.x0 - width, 0.2 * width);
+        assert_close(halo.x1, text.x1 + width, 0.2 * width);
+        assert_close(halo.y0, text.y0 - width, 0.2 * width);
+        assert_close(halo.y1, text.y1 + width, 0.2 * width);
+    }
+}
@@ -82,6 +82,7 @@ pub fn parse(svg_src: &str) -> Outline {
     Outline {
         ctrl_pts: verts,
         bbox,
+        glyphs: Vec::new(),
     }
 }
 
@@ -1,117 +1,294 @@
 use crate::line::Segment;
-use std::f32::consts::PI;
+use glm::Vec2;
 
-#[allow(unused_assignments)]
-pub fn tesselate<'a, S>(mut segments: S, width: f32) -> (Vec<glm::Vec2>, Vec<glm::Vec2>, Vec<u32>)
+/// Points closer than this are considered duplicates.
+const EPS: f32 = 1e-4;
+
+/// Joins where the miter would stick out more than this many half-widths from the line are
+/// not mitered; the line is split in two strips with overlapping caps instead. (Same idea
+/// as the `stroke-miterlimit` of SVG.) This also covers the 180° turns, where the miter is
+/// infinitely long.
+const MITER_LIMIT: f32 = 4.0;
+
+/// The result of tesselating a line. A line may consist of several independent triangle strips,
+/// because the input is broken up at NaNs and very sharp turns.
+#[derive(Debug, Default)]
+pub struct Tesselation {
+    pub verts: Vec<Vec2>,
+    pub uvs: Vec<Vec2>,
+    pub idx: Vec<u32>,
+    /// For every vertex, the index of the point on the input line it was generated from.
+    /// Used to look up per-point attributes. (See `per_vertex`)
+    pub src: Vec<usize>,
+}
+
+/// Tesselate a line into triangles.
+///
+/// The input is sanitized first: Duplicate points are merged, and non-finite points break the
+/// line in pieces, the same way matplotlib handles missing data. Degenerate input (no segments,
+/// everything NaN, non-positive width) produces an empty tesselation.
+pub fn tesselate<'a, S>(segments: S, width: f32) -> Tesselation
 where
     S: Iterator<Item = &'a Segment>,
 {
-    // Consider a pair of segments at a time
-    let mut s1 = None;
-    let mut s2 = None;
+    let mut tess = Tesselation {
+        verts: Vec::with_capacity(256),
+        uvs: Vec::with_capacity(256),
+        idx: Vec::with_capacity(256),
+        src: Vec::with_capacity(256),
+    };
 
-    // Build a vertex- and index-buffer.
-    let mut verts = Vec::with_capacity(256);
-    let mut uvs = Vec::with_capacity(256);
-    let mut length = 0.0;
+    if !(width.is_finite() && width > 0.0) {
+        return tess;
+    }
 
-    // π/2 rotation matrix.
-    let rot = glm::mat2(0.0, -1.0, 1.0, 0.0);
+    // Split the segments into clean polylines, and remember where each point came from.
+    // Segment i goes from point i to point i+1.
+    let mut polyline: Vec<(Vec2, usize)> = Vec::with_capacity(256);
 
-    loop {
-        s1 = s2;
-        s2 = segments.next();
-
-        match (s1, s2) {
-            (None, Some(s)) => {
-                let Segment { p1, .. } = s;
-                let v = s.vec();
-                let l = v.norm();
-
-                // vw-basis for the segments local space (see report).
-                let v = v / l;
-                let w = rot * v;
-
-                // Calculate the new vertices.
-                let v1 = (-width * v) - (width * w) + p1;
-                let v2 = (-width * v) + (width * w) + p1;
-                verts.extend([v1, v2]);
-
-                // Calculate uv-coordinates
-                let uv1 = glm::vec2(0.0, 0.0);
-                let uv2 = glm::vec2(0.0, 1.0);
-                uvs.extend([uv1, uv2]);
-            }
-            (Some(s), None) => {
-                let Segment { p2, .. } = s;
-                let v = s.vec();
-                let l = v.norm();
-                length += l;
-
-                // vw-basis for the segments local space (see report).
-                let v = v / l;
-                let w = rot * v;
-
-                // Calculate the new vertices.
-                let v1 = (width * v) - (width * w) + p2;
-                let v2 = (width * v) + (width * w) + p2;
-                verts.extend([v1, v2]);
-
-                // Calculate uv-coordinates
-                let uv1 = glm::vec2(length, 0.0);
-                let uv2 = glm::vec2(length, 1.0);
-                uvs.extend([uv1, uv2]);
+    for (i, s) in segments.enumerate() {
+        for (p, k) in [(s.p1, i), (s.p2, i + 1)] {
+            if !(p.x.is_finite() && p.y.is_finite()) {
+                // Missing data; end the current line.
+                stroke(&polyline, width, &mut tess);
+                polyline.clear();
+                continue;
             }
-            (Some(s1), Some(s2)) => {
-                let Segment { p1: p2, .. } = s2;
-                let v = s1.vec();
-                let l = v.norm();
-                length += l;
-
-                // vw-basis for the first segments local space (see report).
-                let v = v / l;
-                let w = rot * v;
-
-                // Find the angle the 2nd line segment is turning at.
-                let u = s2.vec();
-                let th = v.angle(&u);
-                let phi = (PI - th) / 2.0;
-                // Calculate the consequtent v-delta.
-                // Note, this has problems: tan(x) explodes
-                let dv = width * f32::tan(PI / 2.0 - phi);
-                // Note that which "side" to add and take away from depends
-                // on which way the line is curving (obviously). The sign of
-                // u's w-component gives this away.
-                let s = f32::signum(w.dot(&u));
-
-                let v1 = s * (dv * v) - (width * w) + p2;
-                let v2 = -s * (dv * v) + (width * w) + p2;
-                verts.extend([v1, v2]);
-
-                // Calculate uv-coordinates
-                let uv1 = glm::vec2(length, 0.0);
-                let uv2 = glm::vec2(length, 1.0);
-                uvs.extend([uv1, uv2]);
+
+            // This also skips the shared point between consecutive segments.
+            if let Some((q, _)) = polyline.last() {
+                if (p - q).norm() <= EPS {
+                    continue;
+                }
             }
-            (None, None) => break,
+
+            polyline.push((p, k));
+        }
+    }
+
+    stroke(&polyline, width, &mut tess);
+
+    tess
+}
+
+/// Stroke a clean polyline (finite points, no duplicates) and append it to the tesselation.
+fn stroke(pts: &[(Vec2, usize)], width: f32, tess: &mut Tesselation) {
+    // A single point does not have a direction, so there is nothing to draw.
+    if pts.len() < 2 {
+        return;
+    }
+
+    // π/2 rotation matrix.
+    let rot = glm::mat2(0.0, -1.0, 1.0, 0.0);
+
+    let mut strip = Strip::begin(tess);
+
+    // Start cap.
+    let (p, k) = pts[0];
+    let v = (pts[1].0 - p).normalize();
+    let w = rot * v;
+    strip.push(tess, -width * v - width * w + p, -width * v + width * w + p, k, 0.0);
+
+    for j in 1..pts.len() - 1 {
+        let (p, k) = pts[j];
+        let incoming = p - pts[j - 1].0;
+        strip.length += incoming.norm();
+
+        // vw-basis for the incoming segments local space (see report), and the
+        // normal of the outgoing segment.
+        let v = incoming.normalize();
+        let w = rot * v;
+        let u = (pts[j + 1].0 - p).normalize();
+        let n = rot * u;
+
+        // The miter points along the bisector of the two normals. It has to be longer than
+        // the width, so the edges stay parallel to the segments. The cosine of half the
+        // turning angle tells how much longer.
+        let m = w + n;
+        let cos = if m.norm() > EPS {
+            m.normalize().dot(&w)
+        } else {
+            0.0
+        };
+
+        if cos * MITER_LIMIT > 1.0 {
+            let miter = (width / cos) * m.normalize();
+            strip.push(tess, p - miter, p + miter, k, strip.length);
+        } else {
+            // Too sharp: End this strip with a cap, and start a new one.
+            strip.push(tess, width * v - width * w + p, width * v + width * w + p, k, strip.length);
+            strip.end(tess);
+            strip = Strip::begin(tess);
+            strip.push(tess, -width * u - width * n + p, -width * u + width * n + p, k, 0.0);
+        }
+    }
+
+    // End cap.
+    let (p, k) = pts[pts.len() - 1];
+    let incoming = p - pts[pts.len() - 2].0;
+    strip.length += incoming.norm();
+    let v = incoming.normalize();
+    let w = rot * v;
+    strip.push(tess, width * v - width * w + p, width * v + width * w + p, k, strip.length);
+    strip.end(tess);
+}
+
+/// Book-keeping for a single triangle strip in the tesselation.
+struct Strip {
+    // Index of the first vertex of the strip.
+    base: usize,
+    length: f32,
+}
+
+impl Strip {
+    fn begin(tess: &Tesselation) -> Strip {
+        Strip {
+            base: tess.verts.len(),
+            length: 0.0,
         }
     }
 
-    // Create the index buffer.
-    // A line of N segments has 2(N+1) vertices.
-    let idx = (0..verts.len() as u32 / 2 - 1)
-        .flat_map(|n| {
+    /// Push a pair of vertices on either side of the line, at the distance `l` along it.
+    fn push(&self, tess: &mut Tesselation, v1: Vec2, v2: Vec2, src: usize, l: f32) {
+        tess.verts.extend([v1, v2]);
+        tess.uvs.extend([glm::vec2(l, 0.0), glm::vec2(l, 1.0)]);
+        tess.src.extend([src, src]);
+    }
+
+    fn end(&self, tess: &mut Tesselation) {
+        // Create the index buffer.
+        // A strip of N segments has 2(N+1) vertices.
+        let n_segments = (tess.verts.len() - self.base) / 2 - 1;
+        let b = self.base as u32;
+        tess.idx.extend((0..n_segments as u32).flat_map(|n| {
             // The N-th line segment has indeces
             //   2N 2N+2 2N+3
             //   2N 2N+3 2N+1
-            [2 * n, 2 * n + 2, 2 * n + 3, 2 * n, 2 * n + 3, 2 * n + 1]
-        })
-        .collect();
+            [2 * n, 2 * n + 2, 2 * n + 3, 2 * n, 2 * n + 3, 2 * n + 1].map(|i| b + i)
+        }));
+
+        // Normalize the uv-coordinates.
+        for uv in tess.uvs[self.base..].iter_mut() {
+            uv.x /= self.length;
+        }
+    }
+}
+
+/// Expand per-point attributes of a line to per-vertex attributes, using the `src` indices of
+/// a tesselation. Missing attributes are padded with the last one, or `default`.
+pub fn per_vertex<T: Copy>(data: &[T], src: &[usize], default: T) -> Vec<T> {
+    let last = data.last().copied().unwrap_or(default);
+    src.iter()
+        .map(|&k| data.get(k).copied().unwrap_or(last))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(pts: &[(f32, f32)], width: f32) -> Tesselation {
+        let pts: Vec<Vec2> = pts.iter().map(|&(x, y)| glm::vec2(x, y)).collect();
+        tesselate(Segment::spline(&pts).segments(), width)
+    }
+
+    /// Everything is finite, and the indices point at vertices.
+    fn check(tess: &Tesselation) {
+        assert_eq!(tess.verts.len(), tess.uvs.len());
+        assert_eq!(tess.verts.len(), tess.src.len());
+        assert_eq!(tess.idx.len() % 3, 0);
+        for v in tess.verts.iter().chain(&tess.uvs) {
+            assert!(v.x.is_finite() && v.y.is_finite(), "non-finite vertex {:?}", v);
+        }
+        assert!(tess.idx.iter().all(|&i| (i as usize) < tess.verts.len()));
+    }
+
+    fn nan() -> f32 {
+        f32::NAN
+    }
+
+    #[test]
+    fn straight_line() {
+        let tess = line(&[(0.0, 0.0), (1.0, 0.0), (2.0, 0.0)], 0.1);
+        check(&tess);
+        assert_eq!(tess.verts.len(), 6);
+        assert_eq!(tess.idx.len(), 12);
+        assert_eq!(tess.src, [0, 0, 1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn duplicate_points_are_merged() {
+        let tess = line(&[(0.0, 0.0), (0.0, 0.5 * EPS), (0.0, 0.0), (1.0, 1.0)], 0.1);
+        check(&tess);
+        assert_eq!(tess.verts.len(), 4);
+        assert_eq!(tess.idx.len(), 6);
+        assert_eq!(tess.src, [0, 0, 3, 3]);
+    }
+
+    #[test]
+    fn nan_breaks_the_line() {
+        let pts = [
+            (0.0, 0.0),
+            (1.0, 0.0),
+            (nan(), 0.0),
+            (2.0, 0.0),
+            (3.0, nan()),
+            (4.0, 0.0),
+            (5.0, 1.0),
+        ];
+        let tess = line(&pts, 0.1);
+        check(&tess);
+        // Two strips of one segment each. The points next to a NaN are alone, and vanish.
+        assert_eq!(tess.verts.len(), 8);
+        assert_eq!(tess.idx.len(), 12);
+        assert_eq!(tess.src, [0, 0, 1, 1, 5, 5, 6, 6]);
+    }
+
+    #[test]
+    fn hairpin_splits_the_strip() {
+        for end in [(0.0, 0.0), (0.0, 0.01), (0.0, -0.01)] {
+            let tess = line(&[(0.0, 0.0), (1.0, 0.0), end], 0.1);
+            check(&tess);
+            assert_eq!(tess.verts.len(), 8);
+            assert_eq!(tess.idx.len(), 12);
+            assert_eq!(tess.src, [0, 0, 1, 1, 1, 1, 2, 2]);
+        }
+    }
+
+    #[test]
+    fn gentle_turn_is_mitered() {
+        let tess = line(&[(0.0, 0.0), (1.0, 0.0), (2.0, 1.0)], 0.1);
+        check(&tess);
+        assert_eq!(tess.verts.len(), 6);
+        // The miter is longer than the width.
+        let miter = (tess.verts[3] - tess.verts[2]).norm() / 2.0;
+        assert!(miter > 0.1 && miter < 0.1 * MITER_LIMIT);
+    }
 
-    // Normalize the uv-coordinates.
-    for uv in uvs.iter_mut() {
-        uv.x /= length;
+    #[test]
+    fn degenerate_input_is_empty() {
+        let square = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)];
+        for width in [0.0, -1.0, nan(), f32::INFINITY] {
+            let tess = line(&square, width);
+            assert!(tess.verts.is_empty() && tess.idx.is_empty() && tess.src.is_empty());
+        }
+
+        for pts in [
+            &[][..],
+            &[(1.0, 1.0)],
+            &[(1.0, 1.0), (1.0, 1.0)],
+            &[(nan(), 0.0), (nan(), nan())],
+            &[(0.0, 0.0), (nan(), 0.0), (1.0, 0.0)],
+        ] {
+            let tess = line(pts, 0.1);
+            assert!(tess.verts.is_empty() && tess.idx.is_empty() && tess.src.is_empty());
+        }
     }
 
-    (verts, uvs, idx)
+    #[test]
+    fn per_vertex_pads_with_last() {
+        let tess = line(&[(0.0, 0.0), (1.0, 0.0), (2.0, 0.0)], 0.1);
+        assert_eq!(per_vertex(&[7, 8], &tess.src, 0), [7, 7, 8, 8, 8, 8]);
+        assert_eq!(per_vertex(&[], &tess.src, 3), [3; 6]);
+    }
 }
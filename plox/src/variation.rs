@@ -0,0 +1,100 @@
+//! Variable fonts.
+//!
+//! A variable font has axes (weight, width, optical size, ...) that the outlines and advances
+//! vary along. An instance of the font is a point on these axes, and the font can name some
+//! of them ("Bold", "Condensed Light"). See `Atlas::with_variations`, `Atlas::instance` and
+//! `Atlas::vary` for building atlases of an instance.
+use rustybuzz::Face;
+pub use rustybuzz::Variation;
+pub use ttf::VariationAxis;
+
+/// A named instance of a variable font.
+#[derive(Debug, Clone)]
+pub struct NamedInstance {
+    pub name: String,
+    /// The position of the instance on each axis.
+    pub coords: Vec<Variation>,
+}
+
+/// A variation of the weight axis. 400 is regular, 700 is bold.
+pub fn weight(value: f32) -> Variation {
+    axis(b"wght", value)
+}
+
+/// A variation of the width axis, in percent of the normal width.
+pub fn width(value: f32) -> Variation {
+    axis(b"wdth", value)
+}
+
+/// A variation of the optical size axis, in points.
+pub fn optical_size(value: f32) -> Variation {
+    axis(b"opsz", value)
+}
+
+pub fn axis(tag: &[u8; 4], value: f32) -> Variation {
+    Variation {
+        tag: ttf::Tag::from_bytes(tag),
+        value,
+    }
+}
+
+/// The axes of a face. Empty if the face is not variable.
+pub fn axes(face: &Face) -> Vec<VariationAxis> {
+    face.variation_axes().into_iter().collect()
+}
+
+/// The named instances of a face. Empty if the face is not variable.
+pub fn named_instances(face: &Face) -> Vec<NamedInstance> {
+    // ttf-parser does not read the instances, so we have to do it ourselves.
+    // (https://learn.microsoft.com/en-us/typography/opentype/spec/fvar)
+    let data = match face.table_data(ttf::Tag::from_bytes(b"fvar")) {
+        Some(data) => data,
+        None => return Vec::new(),
+    };
+
+    let u16_at = |i: usize| data.get(i..i + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    let fixed_at = |i: usize| {
+        data.get(i..i + 4)
+            .map(|b| i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f32 / 65536.0)
+    };
+
+    let header = (u16_at(4), u16_at(8), u16_at(10), u16_at(12), u16_at(14));
+    let (axes_offset, axis_count, axis_size, instance_count, instance_size) = match header {
+        (Some(a), Some(b), Some(c), Some(d), Some(e)) => {
+            (a as usize, b as usize, c as usize, d as usize, e as usize)
+        }
+        _ => return Vec::new(),
+    };
+
+    let tags: Vec<ttf::Tag> = axes(face).iter().map(|a| a.tag).collect();
+    let first = axes_offset + axis_count * axis_size;
+
+    (0..instance_count)
+        .filter_map(|i| {
+            let record = first + i * instance_size;
+            let name_id = u16_at(record)?;
+            let coords = (0..axis_count)
+                .map(|a| {
+                    let value = fixed_at(record + 4 + 4 * a)?;
+                    Some(Variation {
+                        tag: *tags.get(a)?,
+                        value,
+                    })
+                })
+                .collect::<Option<Vec<_>>>()?;
+
+            Some(NamedInstance {
+                name: name(face, name_id).unwrap_or_else(|| format!("Instance {}", i)),
+                coords,
+            })
+        })
+        .collect()
+}
+
+/// Look up a string in the name table.
+fn name(face: &Face, name_id: u16) -> Option<String> {
+    face.names()
+        .into_iter()
+        .filter(|n| n.name_id == name_id)
+        .find_map(|n| n.to_string())
+}